@@ -36,7 +36,7 @@ mod private {
 
 /// Pixel data type.
 /// `u8`, `u16`, `f16` and `f32` are supported.
-pub trait PixelType: private::Sealed + Sized {
+pub trait PixelType: private::Sealed + Sized + Copy + Default + 'static {
     /// Return the C const
     fn pixel_type() -> JxlDataType;
 
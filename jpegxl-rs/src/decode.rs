@@ -24,7 +24,9 @@ use bon::bon;
 use jpegxl_sys::{
     common::types::{JxlDataType, JxlPixelFormat},
     decode::*,
-    metadata::codestream_header::{JxlBasicInfo, JxlOrientation},
+    metadata::codestream_header::{
+        JxlAnimationHeader, JxlBasicInfo, JxlFrameHeader, JxlOrientation,
+    },
 };
 
 use crate::{
@@ -44,6 +46,10 @@ pub type BasicInfo = JxlBasicInfo;
 pub type ProgressiveDetail = JxlProgressiveDetail;
 /// Orientation
 pub type Orientation = JxlOrientation;
+/// Animation header
+pub type AnimationHeader = JxlAnimationHeader;
+/// Frame header
+pub type FrameHeader = JxlFrameHeader;
 
 /// Desired Pixel Format
 #[derive(Clone, Copy, Debug)]
@@ -324,6 +330,214 @@ impl JxlDecoder<'_, '_> {
         }
     }
 
+    /// Decode only the basic info, and the ICC profile if requested
+    #[cfg(any(feature = "image", test))]
+    pub(crate) fn decode_header(
+        &self,
+        data: &[u8],
+        with_icc_profile: bool,
+    ) -> Result<(BasicInfo, Option<Vec<u8>>), DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        let events = {
+            use JxlDecoderStatus::{BasicInfo, ColorEncoding};
+
+            if with_icc_profile {
+                BasicInfo as i32 | ColorEncoding as i32
+            } else {
+                BasicInfo as i32
+            }
+        };
+        check_dec_status(unsafe { JxlDecoderSubscribeEvents(self.dec, events) })?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let mut basic_info = MaybeUninit::uninit();
+        let result = loop {
+            use JxlDecoderStatus as s;
+
+            match unsafe { JxlDecoderProcessInput(self.dec) } {
+                s::BasicInfo => {
+                    check_dec_status(unsafe {
+                        JxlDecoderGetBasicInfo(self.dec, basic_info.as_mut_ptr())
+                    })?;
+                    if !with_icc_profile {
+                        break Ok((unsafe { basic_info.assume_init() }, None));
+                    }
+                }
+                s::ColorEncoding => {
+                    let mut icc = vec![];
+                    self.get_icc_profile(&mut icc)?;
+                    break Ok((unsafe { basic_info.assume_init() }, Some(icc)));
+                }
+                _ => break Err(DecodeError::GenericError),
+            }
+        };
+
+        unsafe { JxlDecoderReset(self.dec) };
+        result
+    }
+
+    /// Return the (decompressed) content of the first box of type `box_type`
+    #[cfg(feature = "image")]
+    pub(crate) fn decode_box(
+        &self,
+        data: &[u8],
+        box_type: [u8; 4],
+    ) -> Result<Option<Vec<u8>>, DecodeError> {
+        use jpegxl_sys::common::types::JxlBoxType;
+
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        check_dec_status(unsafe {
+            JxlDecoderSubscribeEvents(
+                self.dec,
+                JxlDecoderStatus::Box as i32 | JxlDecoderStatus::BoxComplete as i32,
+            )
+        })?;
+        check_dec_status(unsafe { JxlDecoderSetDecompressBoxes(self.dec, true.into()) })?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let mut content: Option<Vec<u8>> = None;
+        let result = loop {
+            use JxlDecoderStatus as s;
+
+            match unsafe { JxlDecoderProcessInput(self.dec) } {
+                s::Box => {
+                    let mut t = JxlBoxType([0; 4]);
+                    check_dec_status(unsafe {
+                        JxlDecoderGetBoxType(self.dec, &mut t, true.into())
+                    })?;
+                    if unsafe { std::mem::transmute::<[std::ffi::c_char; 4], [u8; 4]>(t.0) }
+                        == box_type
+                    {
+                        let buf = content.insert(vec![0; 4096]);
+                        check_dec_status(unsafe {
+                            JxlDecoderSetBoxBuffer(self.dec, buf.as_mut_ptr(), buf.len())
+                        })?;
+                    }
+                }
+                s::BoxNeedMoreOutput => {
+                    // Safety: the event only happens after a buffer is set for the wanted box
+                    let buf = unsafe { content.as_mut().unwrap_unchecked() };
+                    let written = buf.len() - unsafe { JxlDecoderReleaseBoxBuffer(self.dec) };
+                    buf.resize(buf.len() * 2, 0);
+                    check_dec_status(unsafe {
+                        JxlDecoderSetBoxBuffer(
+                            self.dec,
+                            buf.as_mut_ptr().add(written),
+                            buf.len() - written,
+                        )
+                    })?;
+                }
+                s::BoxComplete | s::Success => {
+                    if let Some(buf) = content.as_mut() {
+                        let remaining = unsafe { JxlDecoderReleaseBoxBuffer(self.dec) };
+                        buf.truncate(buf.len() - remaining);
+                    }
+                    break Ok(content);
+                }
+                _ => break Err(DecodeError::GenericError),
+            }
+        };
+
+        unsafe { JxlDecoderReset(self.dec) };
+        result
+    }
+
+    /// Decode all frames, coalesced, as interleaved samples of `data_type`
+    #[cfg(feature = "image")]
+    pub(crate) fn decode_frames(
+        &self,
+        data: &[u8],
+        data_type: JxlDataType,
+        num_channels: u32,
+    ) -> Result<Vec<(FrameHeader, Vec<u8>)>, DecodeError> {
+        if check_valid_signature(data) != Some(true) {
+            return Err(DecodeError::InvalidInput);
+        }
+
+        self.setup_decoder(false, false)?;
+        check_dec_status(unsafe {
+            JxlDecoderSubscribeEvents(
+                self.dec,
+                JxlDecoderStatus::BasicInfo as i32
+                    | JxlDecoderStatus::Frame as i32
+                    | JxlDecoderStatus::FullImage as i32,
+            )
+        })?;
+        check_dec_status(unsafe { JxlDecoderSetCoalescing(self.dec, true.into()) })?;
+        check_dec_status(unsafe { JxlDecoderSetInput(self.dec, data.as_ptr(), data.len()) })?;
+        unsafe { JxlDecoderCloseInput(self.dec) };
+
+        let pixel_format = JxlPixelFormat {
+            num_channels,
+            data_type,
+            endianness: Endianness::Native,
+            align: 0,
+        };
+        let mut basic_info = MaybeUninit::uninit();
+        let mut frames = vec![];
+        let mut header = MaybeUninit::uninit();
+        let mut pixels = vec![];
+        let result = loop {
+            use JxlDecoderStatus as s;
+
+            match unsafe { JxlDecoderProcessInput(self.dec) } {
+                s::BasicInfo => {
+                    check_dec_status(unsafe {
+                        JxlDecoderGetBasicInfo(self.dec, basic_info.as_mut_ptr())
+                    })?;
+
+                    if let Some(pr) = self.parallel_runner {
+                        pr.callback_basic_info(unsafe { &*basic_info.as_ptr() });
+                    }
+                }
+                s::Frame => {
+                    check_dec_status(unsafe {
+                        JxlDecoderGetFrameHeader(self.dec, header.as_mut_ptr())
+                    })?;
+                }
+                s::NeedImageOutBuffer => {
+                    let mut size = 0;
+                    check_dec_status(unsafe {
+                        JxlDecoderImageOutBufferSize(
+                            self.dec,
+                            &raw const pixel_format,
+                            &raw mut size,
+                        )
+                    })?;
+                    pixels.resize(size, 0);
+                    check_dec_status(unsafe {
+                        JxlDecoderSetImageOutBuffer(
+                            self.dec,
+                            &raw const pixel_format,
+                            pixels.as_mut_ptr().cast(),
+                            size,
+                        )
+                    })?;
+                }
+                s::FullImage => {
+                    // Safety: a frame header always precedes the full image
+                    frames.push((
+                        unsafe { header.assume_init_read() },
+                        std::mem::take(&mut pixels),
+                    ));
+                }
+                s::Success => break Ok(frames),
+                _ => break Err(DecodeError::GenericError),
+            }
+        };
+
+        unsafe { JxlDecoderReset(self.dec) };
+        result
+    }
+
     fn setup_decoder(&self, icc: bool, reconstruct_jpeg: bool) -> Result<(), DecodeError> {
         if let Some(runner) = self.parallel_runner {
             check_dec_status(unsafe {
@@ -483,6 +697,32 @@ impl JxlDecoder<'_, '_> {
         Ok((metadata, buf))
     }
 
+    /// Decode a JPEG XL image to an [`Image`] of a specific pixel type,
+    /// keeping its dimensions, row stride, channel layout, ICC profile and orientation
+    ///
+    /// This is the [`Image`]-returning counterpart of [`decode_with`](Self::decode_with).
+    /// It is a separate method so that the existing `(Metadata, Pixels)` and
+    /// `(Metadata, Vec<T>)` return types of [`decode`](Self::decode) and `decode_with` stay
+    /// unchanged. Unlike them, it always requests the ICC profile so the image carries
+    /// its color description.
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when internal decoder fails
+    pub fn decode_image<T: PixelType>(&self, data: &[u8]) -> Result<Image<T>, DecodeError> {
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        let metadata = self.decode_internal(
+            data,
+            Some(T::pixel_type()),
+            true,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+        )?;
+
+        Image::new(metadata, unsafe { &pixel_format.assume_init() }, &buffer)
+    }
+
     /// Reconstruct JPEG data. Fallback to pixels if JPEG reconstruction fails
     ///
     /// # Note
@@ -19,7 +19,7 @@ use half::f16;
 use jpegxl_sys::common::types::{JxlDataType, JxlPixelFormat};
 
 use super::Orientation;
-use crate::common::PixelType;
+use crate::{common::PixelType, DecodeError};
 
 /// Result of decoding
 #[derive(Debug)]
@@ -72,6 +72,225 @@ impl Pixels {
     }
 }
 
+/// Channel layout of interleaved pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelLayout {
+    /// Single luma channel
+    Gray,
+    /// Luma and alpha
+    GrayA,
+    /// Red, green and blue
+    Rgb,
+    /// Red, green, blue and alpha
+    Rgba,
+}
+
+impl ChannelLayout {
+    /// Return the layout with `num_channels` interleaved channels
+    #[must_use]
+    pub fn from_num_channels(num_channels: u32) -> Option<Self> {
+        match num_channels {
+            1 => Some(Self::Gray),
+            2 => Some(Self::GrayA),
+            3 => Some(Self::Rgb),
+            4 => Some(Self::Rgba),
+            _ => None,
+        }
+    }
+
+    /// Number of interleaved channels in a pixel
+    #[must_use]
+    pub fn num_channels(self) -> u32 {
+        match self {
+            Self::Gray => 1,
+            Self::GrayA => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+
+    /// Whether the last channel is alpha
+    #[must_use]
+    pub fn has_alpha(self) -> bool {
+        matches!(self, Self::GrayA | Self::Rgba)
+    }
+
+    /// Whether the color channels are luma only
+    #[must_use]
+    pub fn is_gray(self) -> bool {
+        matches!(self, Self::Gray | Self::GrayA)
+    }
+}
+
+/// Decoded image, owning its pixels together with their layout
+///
+/// Rows are stored `stride` samples apart. When the decoder is asked for an `align`ed
+/// [`PixelFormat`](super::PixelFormat), the padding at the end of each row is kept,
+/// so use [`Image::row`] or [`Image::pixel`] instead of indexing the raw buffer.
+#[derive(Debug, Clone)]
+pub struct Image<T: PixelType> {
+    width: u32,
+    height: u32,
+    layout: ChannelLayout,
+    stride: usize,
+    orientation: Orientation,
+    icc_profile: Option<Vec<u8>>,
+    data: Vec<T>,
+}
+
+impl<T: PixelType> Image<T> {
+    pub(crate) fn new(
+        metadata: Metadata,
+        pixel_format: &JxlPixelFormat,
+        buffer: &[u8],
+    ) -> Result<Self, DecodeError> {
+        debug_assert!(T::pixel_type() == pixel_format.data_type);
+
+        let layout = ChannelLayout::from_num_channels(pixel_format.num_channels)
+            .ok_or(DecodeError::InternalError("Unsupported number of channels"))?;
+        let size = std::mem::size_of::<T>();
+        let row_len = metadata.width as usize * layout.num_channels() as usize;
+        let row_bytes = row_len * size;
+        let stride_bytes = if pixel_format.align > 1 {
+            row_bytes.div_ceil(pixel_format.align) * pixel_format.align
+        } else {
+            row_bytes
+        };
+        let stride = stride_bytes.div_ceil(size);
+
+        let height = metadata.height as usize;
+        let mut data = Vec::with_capacity(stride * height);
+        for y in 0..height {
+            let start = y * stride_bytes;
+            let row = buffer
+                .get(start..start + row_bytes)
+                .ok_or(DecodeError::InternalError("Output buffer is too small"))?;
+            data.extend(T::convert(row, pixel_format));
+            data.resize((y + 1) * stride, T::default());
+        }
+
+        Ok(Self {
+            width: metadata.width,
+            height: metadata.height,
+            layout,
+            stride,
+            orientation: metadata.orientation,
+            icc_profile: metadata.icc_profile,
+            data,
+        })
+    }
+
+    /// Width of the image
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the image
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Channel layout of a pixel
+    #[must_use]
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    /// Number of samples between the start of two consecutive rows,
+    /// including the alignment padding
+    #[must_use]
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Orientation from the metadata.
+    /// The pixels are already re-oriented unless `skip_reorientation` is set on the decoder
+    #[must_use]
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// ICC profile describing the color space of the pixels
+    #[must_use]
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.icc_profile.as_deref()
+    }
+
+    /// Samples of row `y`, without padding
+    #[must_use]
+    pub fn row(&self, y: u32) -> Option<&[T]> {
+        let range = self.row_range(y)?;
+        Some(&self.data[range])
+    }
+
+    /// Mutable samples of row `y`, without padding
+    #[must_use]
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [T]> {
+        let range = self.row_range(y)?;
+        Some(&mut self.data[range])
+    }
+
+    /// Iterate over all rows, without padding
+    #[must_use]
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &[T]> {
+        let row_len = self.row_len();
+        // Guard against `chunks` panicking on an empty image
+        self.data
+            .chunks(self.stride.max(1))
+            .map(move |row| &row[..row_len])
+    }
+
+    /// Channels of the pixel at (`x`, `y`)
+    #[must_use]
+    pub fn pixel(&self, x: u32, y: u32) -> Option<&[T]> {
+        if x >= self.width {
+            return None;
+        }
+        let channels = self.layout.num_channels() as usize;
+        let start = x as usize * channels;
+        self.row(y).map(|row| &row[start..start + channels])
+    }
+
+    /// Raw samples, including the padding at the end of each row
+    #[must_use]
+    pub fn as_raw(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Consume the image and return the raw samples, including the padding at the end of each row
+    #[must_use]
+    pub fn into_raw(self) -> Vec<T> {
+        self.data
+    }
+
+    /// Consume the image and return tightly packed samples
+    #[must_use]
+    pub fn into_packed(mut self) -> Vec<T> {
+        let row_len = self.row_len();
+        if row_len != self.stride {
+            for y in 1..self.height as usize {
+                self.data
+                    .copy_within(y * self.stride..y * self.stride + row_len, y * row_len);
+            }
+            self.data.truncate(row_len * self.height as usize);
+        }
+        self.data
+    }
+
+    fn row_len(&self) -> usize {
+        self.width as usize * self.layout.num_channels() as usize
+    }
+
+    fn row_range(&self, y: u32) -> Option<std::ops::Range<usize>> {
+        (y < self.height).then(|| {
+            let start = y as usize * self.stride;
+            start..start + self.row_len()
+        })
+    }
+}
+
 /// Reconstruction result
 pub enum Data {
     /// JPEG  
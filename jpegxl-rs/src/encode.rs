@@ -17,14 +17,27 @@ along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Encoder of JPEG XL format
 
-use std::{marker::PhantomData, mem::MaybeUninit, ops::Deref, ptr::null};
+use std::{
+    io::{Seek, Write},
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::Deref,
+    ptr::null,
+};
 
 use bon::bon;
 #[allow(clippy::wildcard_imports)]
-use jpegxl_sys::encoder::encode::*;
+use jpegxl_sys::{
+    common::types::{JxlBool, JxlPixelFormat},
+    encoder::encode::*,
+};
 
 use crate::{
-    common::PixelType, errors::EncodeError, memory::MemoryManager, parallel::ParallelRunner,
+    common::PixelType,
+    decode::{Data, Orientation},
+    errors::EncodeError,
+    memory::MemoryManager,
+    parallel::ParallelRunner,
 };
 
 mod options;
@@ -36,12 +49,33 @@ pub use metadata::*;
 mod frame;
 pub use frame::*;
 
+mod output;
+use output::{BufferedOutput, Output, OutputProcessor, SeekOutput};
+
+mod extra_channel;
+pub use extra_channel::*;
+
+mod live;
+pub use live::LiveSession;
+
+mod stats;
+pub use stats::EncoderStats;
+use stats::StatsCollector;
+
+mod chunked;
+pub use chunked::ChunkedFrame;
+use chunked::ChunkedSource;
+
 // MARK: Utility types
 
 /// Encoder result
 pub struct EncoderResult<U: PixelType> {
     /// Output binary data
     pub data: Vec<u8>,
+    /// Stats of the encode, if [`JxlEncoder::collect_stats`] is set
+    pub stats: Option<EncoderStats>,
+    /// Codestream level required by the image, `None` for recompressed JPEG
+    pub required_codestream_level: Option<u32>,
     _pixel_type: PhantomData<U>,
 }
 
@@ -56,6 +90,10 @@ impl<U: PixelType> Deref for EncoderResult<U> {
 // MARK: Encoder
 
 /// JPEG XL Encoder
+///
+/// _Note_: Encoded images never have a preview frame. `JxlBasicInfo` has `have_preview` and
+/// `preview` fields, but libjxl has no encoder function taking the preview pixels, only
+/// `JxlDecoderSetPreviewOutBuffer` on the decoding side
 #[allow(clippy::struct_excessive_bools)]
 pub struct JxlEncoder<'prl, 'mm> {
     /// Opaque pointer to the underlying encoder
@@ -67,6 +105,18 @@ pub struct JxlEncoder<'prl, 'mm> {
     ///
     /// Default: false
     pub has_alpha: bool,
+    /// Whether the color channels are premultiplied by alpha
+    ///
+    /// Default: false
+    pub alpha_premultiplied: bool,
+    /// Bits per sample of the encoded alpha channel
+    ///
+    /// Default: `None`, the same as the color channels
+    pub alpha_bits: Option<u32>,
+    /// Distance for lossy compression of the alpha channel, 0 for lossless
+    ///
+    /// Default: `None`, the distance of the color channels
+    pub alpha_distance: Option<f32>,
     /// Set lossless
     ///
     /// Default: false
@@ -75,6 +125,11 @@ pub struct JxlEncoder<'prl, 'mm> {
     ///
     /// Default: `Squirrel` (7).
     pub speed: EncoderSpeed,
+    /// Allow expert settings, e.g. [`EncoderSpeed::TectonicPlate`], which is very slow but
+    /// gives the smallest lossless files
+    ///
+    /// Default: false
+    pub expert_options: bool,
     /// Set quality for lossy compression: target max butteraugli distance, lower = higher quality
     ///
     ///  Range: 0 .. 15.<br />
@@ -109,15 +164,69 @@ pub struct JxlEncoder<'prl, 'mm> {
     /// Default: 512 KiB
     pub init_buffer_size: usize,
 
+    /// Bit depth of the encoded samples
+    ///
+    /// Default: `None`, the bit depth of the output pixel type
+    pub bit_depth: Option<BitDepth>,
+
     /// Set color encoding
     ///
     /// Default: sRGB for int, Linear sRGB for float
     pub color_encoding: Option<ColorEncoding>,
 
+    /// Set the codestream level, also for recompressed JPEG.
+    /// Encoding fails with [`EncodeError::CodestreamLevel`] if the image needs a higher level
+    ///
+    /// Default: [`CodestreamLevel::Auto`]
+    pub codestream_level: CodestreamLevel,
+
+    /// Options for recompressing JPEG
+    ///
+    /// Default: store the reconstruction data and keep all metadata
+    pub jpeg_options: JpegOptions,
+
+    /// Orientation of the image, applied by decoders
+    ///
+    /// Default: `None`, [`Orientation::Identity`](crate::decode::Orientation::Identity)
+    pub orientation: Option<Orientation>,
+
+    /// Intrinsic width and height, e.g. the CSS size of an image for high density displays
+    ///
+    /// Default: `None`, the size in pixels
+    pub intrinsic_size: Option<(u32, u32)>,
+
     /// Set HDR target intensity.
     /// Specify the target intensity in nits for 1.0 value
     pub target_intensity: Option<f32>,
 
+    /// Extra channels after the alpha channel, e.g. depth or spot colors.
+    /// Their pixels are given per frame with [`EncoderFrame::extra_channel`]
+    ///
+    /// Default: empty
+    pub extra_channels: Vec<ExtraChannel>,
+
+    /// Encode input at a reduced resolution, which decoders upsample.
+    /// Overrides the resampling of [`Self::frame_settings`]
+    ///
+    /// Default: `None`
+    pub downsampled: Option<Downsampled>,
+
+    /// Coding tool settings, reapplied on every encode
+    ///
+    /// Default: all chosen by the encoder
+    pub frame_settings: FrameSettings,
+
+    /// Encode frames as an animation.
+    /// Set the duration of each frame with [`EncoderFrame::duration`]
+    ///
+    /// Default: `None`, and frames are layers of a still image
+    pub animation: Option<Animation>,
+
+    /// Collect [`EncoderStats`] in the [`EncoderResult`]
+    ///
+    /// Default: false
+    pub collect_stats: bool,
+
     /// Set parallel runner
     ///
     /// Default: `None`, indicating single thread execution
@@ -126,6 +235,18 @@ pub struct JxlEncoder<'prl, 'mm> {
     /// Whether box is used in encoder
     use_box: bool,
 
+    /// Options set with [`Self::set_frame_option`] and [`Self::set_frame_float_option`]
+    frame_options: Vec<FrameOption>,
+
+    /// Stats of the current encode
+    stats: Option<StatsCollector>,
+    /// Stats of the last finished encode
+    last_stats: Option<EncoderStats>,
+    /// Codestream level required by the current image
+    required_level: Option<u32>,
+    /// Size of full frames of the current image
+    frame_size: (u32, u32),
+
     /// Set memory manager
     #[allow(dead_code)]
     memory_manager: Option<&'mm dyn MemoryManager>,
@@ -141,15 +262,29 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
     pub fn new(
         memory_manager: Option<&'mm dyn MemoryManager>,
         #[builder(default)] has_alpha: bool,
+        #[builder(default)] alpha_premultiplied: bool,
+        alpha_bits: Option<u32>,
+        alpha_distance: Option<f32>,
         lossless: Option<bool>,
         #[builder(default)] speed: EncoderSpeed,
+        #[builder(default)] expert_options: bool,
         #[builder(default = 1.0)] quality: f32,
         #[builder(default)] use_container: bool,
         #[builder(default)] uses_original_profile: bool,
         #[builder(default)] decoding_speed: i64,
         init_buffer_size: Option<usize>,
+        bit_depth: Option<BitDepth>,
         color_encoding: Option<ColorEncoding>,
+        #[builder(default)] codestream_level: CodestreamLevel,
+        #[builder(default)] jpeg_options: JpegOptions,
+        orientation: Option<Orientation>,
+        intrinsic_size: Option<(u32, u32)>,
         target_intensity: Option<f32>,
+        #[builder(default)] extra_channels: Vec<ExtraChannel>,
+        downsampled: Option<Downsampled>,
+        #[builder(default)] frame_settings: FrameSettings,
+        animation: Option<Animation>,
+        #[builder(default)] collect_stats: bool,
         parallel_runner: Option<&'prl dyn ParallelRunner>,
         #[builder(default)] use_box: bool,
     ) -> Result<Self, EncodeError> {
@@ -170,17 +305,36 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
             enc,
             options_ptr,
             has_alpha,
+            alpha_premultiplied,
+            alpha_bits,
+            alpha_distance,
             lossless,
             speed,
+            expert_options,
             quality,
             use_container,
             uses_original_profile,
             decoding_speed,
             init_buffer_size: init_buffer_size.map_or(512 * 1024, |v| if v < 32 { 32 } else { v }),
+            bit_depth,
             color_encoding,
+            codestream_level,
+            jpeg_options,
+            orientation,
+            intrinsic_size,
             target_intensity,
+            extra_channels,
+            downsampled,
+            frame_settings,
+            animation,
+            collect_stats,
             parallel_runner,
             use_box,
+            frame_options: vec![],
+            stats: None,
+            last_stats: None,
+            required_level: None,
+            frame_size: (0, 0),
             memory_manager,
         })
     }
@@ -223,7 +377,13 @@ impl JxlEncoder<'_, '_> {
     }
 
     // Set options
-    fn set_options(&self) -> Result<(), EncodeError> {
+    fn set_options(&mut self) -> Result<(), EncodeError> {
+        self.required_level = None;
+        if self.expert_options {
+            unsafe { JxlEncoderAllowExpertOptions(self.enc) };
+        } else if matches!(self.speed, EncoderSpeed::TectonicPlate) {
+            return Err(EncodeError::ApiUsage);
+        }
         self.check_enc_status(unsafe {
             JxlEncoderUseContainer(self.enc, self.use_container.into())
         })?;
@@ -250,17 +410,119 @@ impl JxlEncoder<'_, '_> {
             )
         })?;
 
+        self.frame_settings.validate()?;
+        for option in self
+            .frame_settings
+            .options()
+            .iter()
+            .chain(&self.frame_options)
+        {
+            self.set_option(*option)?;
+        }
+
+        if let Some(Downsampled { factor, .. }) = self.downsampled {
+            let factor = factor as i64;
+            self.set_option(FrameOption::Int(
+                JxlEncoderFrameSettingId::Resampling,
+                factor,
+            ))?;
+            self.set_option(FrameOption::Int(
+                JxlEncoderFrameSettingId::ExtraChannelResampling,
+                factor,
+            ))?;
+            self.set_option(FrameOption::Int(
+                JxlEncoderFrameSettingId::AlreadyDownsampled,
+                1,
+            ))?;
+        }
+
+        if self.collect_stats {
+            // Kept until the encoder is reset, as queued frames may refer to it
+            if self.stats.is_none() {
+                self.stats = Some(StatsCollector::new()?);
+            }
+            if let Some(stats) = &self.stats {
+                unsafe { JxlEncoderCollectStats(self.options_ptr, stats.as_ptr()) };
+            }
+        }
+
+        Ok(())
+    }
+
+    // Set a raw frame option
+    fn set_option(&self, option: FrameOption) -> Result<(), EncodeError> {
+        self.check_enc_status(unsafe {
+            match option {
+                FrameOption::Int(id, value) => {
+                    JxlEncoderFrameSettingsSetOption(self.options_ptr, id, value)
+                }
+                FrameOption::Float(id, value) => {
+                    JxlEncoderFrameSettingsSetFloatOption(self.options_ptr, id, value)
+                }
+            }
+        })
+    }
+
+    // Set a raw frame option, keeping it for later encodes
+    fn keep_option(&mut self, option: FrameOption) -> Result<(), EncodeError> {
+        self.set_option(option)?;
+        self.frame_options.retain(|o| o.id() != option.id());
+        self.frame_options.push(option);
+        Ok(())
+    }
+
+    // The K channel of CMYK is an extra channel
+    fn check_cmyk(&self) -> Result<(), EncodeError> {
+        let has_black = self
+            .extra_channels
+            .iter()
+            .any(|c| matches!(c.channel_type, ExtraChannelType::Black));
+        if self
+            .color_encoding
+            .as_ref()
+            .is_some_and(ColorEncoding::is_cmyk)
+            && !has_black
+        {
+            return Err(EncodeError::ApiUsage);
+        }
         Ok(())
     }
 
-    // Setup the encoder
+    // Reset the encoder after an error, so that it can be used again
+    fn reset_on_error<R>(&mut self, result: Result<R, EncodeError>) -> Result<R, EncodeError> {
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    // Setup the encoder, resetting it on error
     fn setup_encoder(
-        &self,
+        &mut self,
+        width: u32,
+        height: u32,
+        bits: (u32, u32),
+        has_alpha: bool,
+    ) -> Result<(), EncodeError> {
+        let result = self.configure(width, height, bits, has_alpha);
+        self.reset_on_error(result)
+    }
+
+    // Set the basic info and the settings of the image
+    fn configure(
+        &mut self,
         width: u32,
         height: u32,
         (bits, exp): (u32, u32),
         has_alpha: bool,
     ) -> Result<(), EncodeError> {
+        let (bits, exp) = match self.bit_depth {
+            Some(depth) => depth.bits()?,
+            None => (bits, exp),
+        };
+
+        self.check_cmyk()?;
+
         if let Some(runner) = self.parallel_runner {
             unsafe {
                 self.check_enc_status(JxlEncoderSetParallelRunner(
@@ -281,65 +543,263 @@ impl JxlEncoder<'_, '_> {
 
         basic_info.xsize = width;
         basic_info.ysize = height;
+        self.frame_size = match self.downsampled {
+            Some(Downsampled { factor, .. }) => {
+                let factor = factor as u32;
+                (width.div_ceil(factor), height.div_ceil(factor))
+            }
+            None => (width, height),
+        };
         basic_info.have_container = self.use_container.into();
         basic_info.uses_original_profile = self.uses_original_profile.into();
 
         basic_info.bits_per_sample = bits;
         basic_info.exponent_bits_per_sample = exp;
 
+        let num_extra_channels =
+            u32::try_from(self.extra_channels.len()).map_err(|_| EncodeError::NotSupported)?;
+        basic_info.num_extra_channels = u32::from(has_alpha) + num_extra_channels;
         if has_alpha {
-            basic_info.num_extra_channels = 1;
-            basic_info.alpha_bits = bits;
-            basic_info.alpha_exponent_bits = exp;
+            if let Some(alpha_bits) = self.alpha_bits {
+                basic_info.alpha_bits = alpha_bits;
+                basic_info.alpha_exponent_bits = 0;
+            } else {
+                basic_info.alpha_bits = bits;
+                basic_info.alpha_exponent_bits = exp;
+            }
+            basic_info.alpha_premultiplied = self.alpha_premultiplied.into();
         } else {
-            basic_info.num_extra_channels = 0;
             basic_info.alpha_bits = 0;
             basic_info.alpha_exponent_bits = 0;
         }
 
-        if let Some(ColorEncoding::SrgbLuma | ColorEncoding::LinearSrgbLuma) = self.color_encoding {
+        if self
+            .color_encoding
+            .as_ref()
+            .is_some_and(ColorEncoding::is_gray)
+        {
             basic_info.num_color_channels = 1;
         }
 
+        if let Some(orientation) = self.orientation {
+            basic_info.orientation = orientation;
+        }
+        if let Some((width, height)) = self.intrinsic_size {
+            basic_info.intrinsic_xsize = width;
+            basic_info.intrinsic_ysize = height;
+        }
+
         if let Some(target_intensity) = self.target_intensity {
             basic_info.intensity_target = target_intensity;
         }
 
+        if let Some(animation) = &self.animation {
+            basic_info.have_animation = true.into();
+            basic_info.animation = animation.into();
+        }
+
         if let Some(pr) = self.parallel_runner {
             pr.callback_basic_info(&basic_info);
         }
 
         self.check_enc_status(unsafe { JxlEncoderSetBasicInfo(self.enc, &raw const basic_info) })?;
 
-        if let Some(color_encoding) = &self.color_encoding {
+        if let Some(depth) = self.bit_depth {
             self.check_enc_status(unsafe {
-                JxlEncoderSetColorEncoding(self.enc, &color_encoding.into())
+                JxlEncoderSetFrameBitDepth(self.options_ptr, &depth.input())
             })?;
         }
+
+        if let Some(Downsampled { factor, upsampling }) = self.downsampled {
+            self.check_enc_status(unsafe {
+                JxlEncoderSetUpsamplingMode(self.enc, factor as i64, upsampling as i64)
+            })?;
+        }
+
+        self.set_color_encoding()?;
+
+        if let (true, Some(distance)) = (has_alpha, self.alpha_distance) {
+            self.check_enc_status(unsafe {
+                JxlEncoderSetExtraChannelDistance(self.options_ptr, 0, distance)
+            })?;
+        }
+
+        self.set_extra_channels((bits, exp), has_alpha)?;
+
+        self.set_codestream_level()
+    }
+
+    // Set the color encoding or the ICC profile
+    fn set_color_encoding(&self) -> Result<(), EncodeError> {
+        match self.color_encoding.as_ref().map(ColorEncoding::to_jxl) {
+            Some(Ok(color_encoding)) => self.check_enc_status(unsafe {
+                JxlEncoderSetColorEncoding(self.enc, &raw const color_encoding)
+            })?,
+            Some(Err(profile)) => {
+                ColorEncoding::validate_icc(profile)?;
+                self.check_enc_status(unsafe {
+                    JxlEncoderSetICCProfile(self.enc, profile.as_ptr(), profile.len())
+                })
+                .map_err(|e| match e {
+                    EncodeError::BadInput => EncodeError::InvalidIccProfile,
+                    e => e,
+                })?;
+            }
+            None => (),
+        }
         Ok(())
     }
 
-    // Add a frame
-    fn add_frame<T: PixelType>(&self, frame: &EncoderFrame<T>) -> Result<(), EncodeError> {
+    // Set the info of the extra channels after alpha
+    fn set_extra_channels(
+        &self,
+        (bits, exp): (u32, u32),
+        has_alpha: bool,
+    ) -> Result<(), EncodeError> {
+        for (i, channel) in self.extra_channels.iter().enumerate() {
+            let index = i + usize::from(has_alpha);
+            self.check_enc_status(unsafe {
+                JxlEncoderSetExtraChannelInfo(self.enc, index, &channel.info((bits, exp)))
+            })?;
+            if let Some(name) = &channel.name {
+                self.check_enc_status(unsafe {
+                    JxlEncoderSetExtraChannelName(self.enc, index, name.as_ptr(), name.len())
+                })?;
+            }
+            if let Some(distance) = channel.distance {
+                self.check_enc_status(unsafe {
+                    JxlEncoderSetExtraChannelDistance(self.options_ptr, index, distance)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    // Check the level required by the basic info against the one set
+    fn set_codestream_level(&mut self) -> Result<(), EncodeError> {
+        let required = unsafe { JxlEncoderGetRequiredCodestreamLevel(self.enc) };
+        let required = u32::try_from(required).ok();
+        match (self.codestream_level, required) {
+            (_, None) | (CodestreamLevel::Level5, Some(6..)) => {
+                return Err(EncodeError::CodestreamLevel { required });
+            }
+            _ => (),
+        }
+        self.check_enc_status(unsafe {
+            JxlEncoderSetCodestreamLevel(self.enc, self.codestream_level as i32)
+        })?;
+        self.required_level = required;
+        Ok(())
+    }
+
+    // Add a frame, resetting the encoder on error
+    fn add_frame<T: PixelType>(&mut self, frame: &EncoderFrame<T>) -> Result<(), EncodeError> {
+        let result = self.add_image_frame(frame);
+        self.reset_on_error(result)
+    }
+
+    // Add the pixels of a frame
+    fn add_image_frame<T: PixelType>(&self, frame: &EncoderFrame<T>) -> Result<(), EncodeError> {
+        let layer = frame.header.as_ref().map(|h| &h.layer_info);
+        if self.downsampled.is_some()
+            && !layer.is_some_and(|l| l.have_crop == JxlBool::True)
+            && !frame.has_size(self.frame_size.0, self.frame_size.1)
+        {
+            return Err(EncodeError::BadInput);
+        }
+        // A separate alpha plane needs the alpha channel declared in the basic info
+        if frame.alpha.is_some() && !self.has_alpha {
+            return Err(EncodeError::ApiUsage);
+        }
+        let options = self.frame_options_for(frame)?;
+        // Always set the header and name, as they would persist from a previous frame
+        let header = frame.header.clone().unwrap_or_else(default_header);
+        self.check_enc_status(unsafe { JxlEncoderSetFrameHeader(options, &raw const header) })?;
+        let name = frame.c_name().transpose()?.unwrap_or_default();
+        self.check_enc_status(unsafe { JxlEncoderSetFrameName(options, name.as_ptr().cast()) })?;
         self.check_enc_status(unsafe {
             JxlEncoderAddImageFrame(
-                self.options_ptr,
+                options,
                 &frame.pixel_format(),
                 frame.data.as_ptr().cast(),
                 std::mem::size_of_val(frame.data),
             )
-        })
+        })?;
+
+        let pixel_format = JxlPixelFormat {
+            num_channels: 1,
+            ..frame.pixel_format()
+        };
+        if let Some((data_type, data)) = frame.alpha {
+            self.check_enc_status(unsafe {
+                JxlEncoderSetExtraChannelBuffer(
+                    options,
+                    &JxlPixelFormat {
+                        data_type,
+                        ..pixel_format
+                    },
+                    data.as_ptr().cast(),
+                    data.len(),
+                    0,
+                )
+            })?;
+        }
+        for (i, data) in (u32::from(self.has_alpha)..).zip(&frame.extra_channels) {
+            self.check_enc_status(unsafe {
+                JxlEncoderSetExtraChannelBuffer(
+                    options,
+                    &raw const pixel_format,
+                    data.as_ptr().cast(),
+                    std::mem::size_of_val(*data),
+                    i,
+                )
+            })?;
+        }
+        Ok(())
     }
 
-    // Add a frame from JPEG raw data
-    fn add_jpeg_frame(&self, data: &[u8]) -> Result<(), EncodeError> {
-        self.check_enc_status(unsafe {
+    // Settings for a frame, cloned from the encoder's settings if the frame overrides them.
+    // The clone is owned by the encoder
+    fn frame_options_for<T: PixelType>(
+        &self,
+        frame: &EncoderFrame<T>,
+    ) -> Result<*mut JxlEncoderFrameSettings, EncodeError> {
+        if !frame.has_settings() {
+            return Ok(self.options_ptr);
+        }
+
+        let options = unsafe { JxlEncoderFrameSettingsCreate(self.enc, self.options_ptr) };
+        if options.is_null() {
+            return Err(EncodeError::OutOfMemory);
+        }
+        if let Some(lossless) = frame.lossless {
+            self.check_enc_status(unsafe { JxlEncoderSetFrameLossless(options, lossless.into()) })?;
+        }
+        if let Some(speed) = frame.speed {
+            self.check_enc_status(unsafe {
+                JxlEncoderFrameSettingsSetOption(
+                    options,
+                    JxlEncoderFrameSettingId::Effort,
+                    speed as _,
+                )
+            })?;
+        }
+        if let Some(quality) = frame.quality {
+            self.check_enc_status(unsafe { JxlEncoderSetFrameDistance(options, quality) })?;
+        }
+        Ok(options)
+    }
+
+    // Add a frame from JPEG raw data, resetting the encoder on error
+    fn add_jpeg_frame(&mut self, data: &[u8]) -> Result<(), EncodeError> {
+        let result = self.check_enc_status(unsafe {
             JxlEncoderAddJPEGFrame(
                 self.options_ptr,
                 data.as_ptr().cast(),
                 std::mem::size_of_val(data),
             )
-        })
+        });
+        self.reset_on_error(result)
     }
 
     fn internal(&mut self) -> Result<Vec<u8>, EncodeError> {
@@ -368,19 +828,118 @@ impl JxlEncoder<'_, '_> {
             }
         }
         buffer.truncate(next_out as usize - buffer.as_ptr() as usize);
-        self.check_enc_status(status)?;
+        let result = self.check_enc_status(status);
+        self.reset();
+        result?;
+
+        buffer.shrink_to_fit();
+        Ok(buffer)
+    }
 
+    // Reset the encoder and recreate the frame settings
+    fn reset(&mut self) {
+        self.last_stats = self.stats.as_ref().map(StatsCollector::stats);
         unsafe { JxlEncoderReset(self.enc) };
+        self.stats = None;
         self.options_ptr = unsafe { JxlEncoderFrameSettingsCreate(self.enc, null()) };
+    }
 
-        buffer.shrink_to_fit();
-        Ok(buffer)
+    // Add a frame from a chunked source, closing the input. Reset the encoder on error
+    fn add_chunked_frame<C: ChunkedFrame>(&mut self, frame: &mut C) -> Result<(), EncodeError> {
+        let source = ChunkedSource::new(frame);
+        let status = self.check_enc_status(unsafe {
+            JxlEncoderAddChunkedFrame(self.options_ptr, true.into(), source.as_raw())
+        });
+        // A failure of the frame explains a failing status
+        let result = source.check().and(status);
+        self.reset_on_error(result)
+    }
+
+    // Add input with `add`, streaming the output through an output processor
+    fn stream(
+        &mut self,
+        output: impl Output,
+        add: impl FnOnce(&mut Self) -> Result<(), EncodeError>,
+    ) -> Result<(), EncodeError> {
+        let mut processor = OutputProcessor::new(output);
+        let result = (|| {
+            self.check_enc_status(unsafe {
+                JxlEncoderSetOutputProcessor(self.enc, processor.as_raw())
+            })?;
+            add(self)?;
+            unsafe { JxlEncoderCloseInput(self.enc) };
+            self.check_enc_status(unsafe { JxlEncoderFlushInput(self.enc) })
+        })();
+        // The encoder must not call the processor once it is dropped
+        self.reset();
+
+        let written = processor.finish();
+        result?;
+        Ok(written?)
+    }
+
+    // Encode a single frame stored with `bits` bits per sample
+    #[cfg(feature = "image")]
+    pub(crate) fn encode_frame_with_bits<T: PixelType>(
+        &mut self,
+        frame: &EncoderFrame<T>,
+        width: u32,
+        height: u32,
+        bits: (u32, u32),
+    ) -> Result<Vec<u8>, EncodeError> {
+        self.setup_encoder(width, height, bits, self.has_alpha)?;
+        self.add_frame(frame)?;
+        self.internal()
+    }
+
+    // Encode 8-bit frames
+    #[cfg(feature = "image")]
+    pub(crate) fn encode_frames(
+        &mut self,
+        frames: &[EncoderFrame<u8>],
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, EncodeError> {
+        self.setup_encoder(width, height, (8, 0), self.has_alpha)?;
+        for frame in frames {
+            self.add_frame(frame)?;
+        }
+        self.internal()
+    }
+
+    // Set the settings for recompressing a JPEG
+    fn configure_jpeg(&mut self, options: JpegOptions) -> Result<(), EncodeError> {
+        if let Some(runner) = self.parallel_runner {
+            unsafe {
+                self.check_enc_status(JxlEncoderSetParallelRunner(
+                    self.enc,
+                    runner.runner(),
+                    runner.as_opaque_ptr(),
+                ))?;
+            }
+        }
+
+        self.set_options()?;
+        for option in options.options() {
+            self.set_option(option)?;
+        }
+
+        // If using container format, store JPEG reconstruction metadata
+        self.check_enc_status(unsafe {
+            JxlEncoderStoreJPEGMetadata(self.enc, options.store_reconstruction.into())
+        })?;
+        // The basic info comes from the JPEG, so the required level is not known beforehand
+        self.check_enc_status(unsafe {
+            JxlEncoderSetCodestreamLevel(self.enc, self.codestream_level as i32)
+        })
     }
 
     // Start encoding
     fn start_encoding<U: PixelType>(&mut self) -> Result<EncoderResult<U>, EncodeError> {
         Ok(EncoderResult {
             data: self.internal()?,
+            stats: self.last_stats.take(),
+            required_codestream_level: self.required_level.take(),
             _pixel_type: PhantomData,
         })
     }
@@ -388,7 +947,7 @@ impl JxlEncoder<'_, '_> {
 
 // MARK: Public interface
 impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
-    /// Set a specific encoder frame setting
+    /// Set a specific encoder frame setting, kept for all later encodes
     ///
     /// # Errors
     /// Return [`EncodeError`] if it fails to set frame option
@@ -397,9 +956,19 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
         option: JxlEncoderFrameSettingId,
         value: i64,
     ) -> Result<(), EncodeError> {
-        self.check_enc_status(unsafe {
-            JxlEncoderFrameSettingsSetOption(self.options_ptr, option, value)
-        })
+        self.keep_option(FrameOption::Int(option, value))
+    }
+
+    /// Set a specific encoder frame setting with a float value, kept for all later encodes
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if it fails to set frame option
+    pub fn set_frame_float_option(
+        &mut self,
+        option: JxlEncoderFrameSettingId,
+        value: f32,
+    ) -> Result<(), EncodeError> {
+        self.keep_option(FrameOption::Float(option, value))
     }
 
     /// Return a wrapper type for adding multiple frames to the encoder
@@ -415,6 +984,21 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
         Ok(MultiFrames::<'enc, 'prl, 'mm, U>(self, PhantomData))
     }
 
+    /// Start a session adding frames over time, writing the output to `writer` as it
+    /// becomes final
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if it fails to set up the encoder
+    pub fn live<'enc, U: PixelType, W: Write>(
+        &'enc mut self,
+        width: u32,
+        height: u32,
+        writer: W,
+    ) -> Result<LiveSession<'enc, 'prl, 'mm, W>, EncodeError> {
+        self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
+        LiveSession::new(self, writer)
+    }
+
     /// Add a metadata box to the encoder
     ///
     /// # Errors
@@ -441,30 +1025,31 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
         })
     }
 
-    /// Encode a JPEG XL image from existing raw JPEG data
+    /// Encode a JPEG XL image from existing raw JPEG data, with [`Self::jpeg_options`]
     ///
     /// Note: Only support output pixel type of `u8`. Ignore alpha channel settings
     ///
     /// # Errors
-    /// Return [`EncodeError`] if the internal encoder fails to encode
+    /// Return [`EncodeError`] if the internal encoder fails to encode,
+    /// or [`EncodeError::JpegVerification`] if the verification fails
     pub fn encode_jpeg(&mut self, data: &[u8]) -> Result<EncoderResult<u8>, EncodeError> {
-        if let Some(runner) = self.parallel_runner {
-            unsafe {
-                self.check_enc_status(JxlEncoderSetParallelRunner(
-                    self.enc,
-                    runner.runner(),
-                    runner.as_opaque_ptr(),
-                ))?;
-            }
-        }
-
-        self.set_options()?;
-
-        // If using container format, store JPEG reconstruction metadata
-        self.check_enc_status(unsafe { JxlEncoderStoreJPEGMetadata(self.enc, true.into()) })?;
+        let options = self.jpeg_options;
+        options.validate()?;
 
+        let result = self.configure_jpeg(options);
+        self.reset_on_error(result)?;
         self.add_jpeg_frame(data)?;
-        self.start_encoding()
+        let result = self.start_encoding()?;
+
+        if options.verify {
+            let reconstructed = crate::decoder_builder()
+                .build()
+                .and_then(|decoder| decoder.reconstruct(&result));
+            if !matches!(reconstructed, Ok((_, Data::Jpeg(jpeg))) if jpeg == data) {
+                return Err(EncodeError::JpegVerification);
+            }
+        }
+        Ok(result)
     }
 
     /// Encode a JPEG XL image from pixels
@@ -500,6 +1085,139 @@ impl<'prl, 'mm> JxlEncoder<'prl, 'mm> {
         self.add_frame(frame)?;
         self.start_encoding::<U>()
     }
+
+    /// Encode a JPEG XL image from a frame within a size budget, searching for the quality
+    /// with up to `max_iterations` encodes.
+    ///
+    /// Return the largest encode within the budget and the distance it used.
+    ///
+    /// # Errors
+    /// Return [`EncodeError::ApiUsage`] if `max_iterations` is 0, or if [`Self::lossless`]
+    /// or the quality or lossless setting of the frame is set, as they override the distance.
+    /// Return [`EncodeError::SizeBudget`] if even the lowest quality is over the budget,
+    /// or [`EncodeError`] if the internal encoder fails to encode
+    pub fn encode_with_budget<T: PixelType, U: PixelType>(
+        &mut self,
+        frame: &EncoderFrame<T>,
+        width: u32,
+        height: u32,
+        target: SizeTarget,
+        max_iterations: u32,
+    ) -> Result<(EncoderResult<U>, f32), EncodeError> {
+        const MIN_DISTANCE: f32 = 0.01;
+        const MAX_DISTANCE: f32 = 25.0;
+
+        if max_iterations == 0
+            || self.lossless == Some(true)
+            || frame.quality.is_some()
+            || frame.lossless.is_some()
+        {
+            return Err(EncodeError::ApiUsage);
+        }
+
+        let max_bytes = target.max_bytes(width, height);
+        let quality = self.quality;
+        let result = (|| {
+            let mut best: Option<(EncoderResult<U>, f32)> = None;
+            let (mut low, mut high) = (MIN_DISTANCE, MAX_DISTANCE);
+            for i in 0..max_iterations {
+                // Start with the smallest size, then bisect on a log scale
+                let distance = if i == 0 {
+                    MAX_DISTANCE
+                } else {
+                    (low * high).sqrt()
+                };
+                self.quality = distance;
+                let result: EncoderResult<U> = self.encode_frame(frame, width, height)?;
+                if result.len() <= max_bytes {
+                    // Sizes are not strictly monotonic in the distance
+                    if best
+                        .as_ref()
+                        .is_none_or(|(best, _)| result.len() > best.len())
+                    {
+                        best = Some((result, distance));
+                    }
+                    high = distance;
+                } else if i == 0 {
+                    break;
+                } else {
+                    low = distance;
+                }
+            }
+            best.ok_or(EncodeError::SizeBudget)
+        })();
+        self.quality = quality;
+        result
+    }
+
+    /// Encode a JPEG XL image from a frame, streaming the output to `writer`
+    /// instead of keeping it in memory.
+    /// The writer is left at the end of the image.
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if the internal encoder fails to encode or writing fails
+    pub fn encode_to_writer<T: PixelType, U: PixelType>(
+        &mut self,
+        frame: &EncoderFrame<T>,
+        width: u32,
+        height: u32,
+        writer: impl Write + Seek,
+    ) -> Result<(), EncodeError> {
+        let output = SeekOutput::new(writer)?;
+        self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
+        self.stream(output, |enc| enc.add_frame(frame))
+    }
+
+    /// Encode a JPEG XL image from a frame, streaming the output to a non-seekable `writer`.
+    /// Only the output the encoder may still rewrite is kept in memory.
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if the internal encoder fails to encode or writing fails
+    pub fn encode_to_stream<T: PixelType, U: PixelType>(
+        &mut self,
+        frame: &EncoderFrame<T>,
+        width: u32,
+        height: u32,
+        writer: impl Write,
+    ) -> Result<(), EncodeError> {
+        self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
+        self.stream(BufferedOutput::new(writer), |enc| enc.add_frame(frame))
+    }
+
+    /// Encode a JPEG XL image from a [`ChunkedFrame`], requesting its pixels in rectangles.
+    /// The whole output is kept in memory; use [`Self::encode_chunked_to_writer`] to stream it
+    ///
+    /// # Errors
+    /// Return [`EncodeError::ChunkedFrame`] if the frame fails,
+    /// or [`EncodeError`] if the internal encoder fails to encode
+    pub fn encode_chunked<C: ChunkedFrame, U: PixelType>(
+        &mut self,
+        frame: &mut C,
+        width: u32,
+        height: u32,
+    ) -> Result<EncoderResult<U>, EncodeError> {
+        self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
+        self.add_chunked_frame(frame)?;
+        self.start_encoding::<U>()
+    }
+
+    /// Encode a JPEG XL image from a [`ChunkedFrame`], streaming the output to `writer`.
+    /// Neither the pixels nor the output have to be in memory at once.
+    ///
+    /// # Errors
+    /// Return [`EncodeError::ChunkedFrame`] if the frame fails,
+    /// or [`EncodeError`] if the internal encoder fails to encode or writing fails
+    pub fn encode_chunked_to_writer<C: ChunkedFrame, U: PixelType>(
+        &mut self,
+        frame: &mut C,
+        width: u32,
+        height: u32,
+        writer: impl Write + Seek,
+    ) -> Result<(), EncodeError> {
+        let output = SeekOutput::new(writer)?;
+        self.setup_encoder(width, height, U::bits_per_sample(), self.has_alpha)?;
+        self.stream(output, |enc| enc.add_chunked_frame(frame))
+    }
 }
 
 impl Drop for JxlEncoder<'_, '_> {
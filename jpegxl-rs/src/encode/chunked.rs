@@ -0,0 +1,205 @@
+use std::{
+    ffi::c_void,
+    panic::{self, AssertUnwindSafe},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use jpegxl_sys::{
+    common::types::{JxlEndianness, JxlPixelFormat},
+    encoder::encode::JxlChunkedFrameInputSource,
+};
+
+use super::output::panic_message;
+use crate::{common::PixelType, EncodeError};
+
+/// A frame supplying its pixels in rectangles on demand, so the whole input image never has
+/// to be in memory
+///
+/// The encoder requests rectangles at multiples of 8 pixels, of at most 2048 x 2048 pixels,
+/// possibly from the threads of the parallel runner. Rows of the returned data are tightly
+/// packed, and must hold exactly `width * height` pixels. Otherwise, or if a method panics,
+/// encoding fails with [`EncodeError::ChunkedFrame`].
+pub trait ChunkedFrame: Send {
+    /// Pixel type of the samples
+    type Pixel: PixelType;
+
+    /// Number of interleaved color channels, including alpha
+    fn num_channels(&self) -> u32;
+
+    /// Return the color channels of the `width` x `height` rectangle at (`x`, `y`)
+    fn get_color_channel_data_at(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Vec<Self::Pixel>;
+
+    /// Return the extra channel `index` of the `width` x `height` rectangle at (`x`, `y`)
+    ///
+    /// Default: all zeros
+    fn get_extra_channel_data_at(
+        &mut self,
+        index: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Vec<Self::Pixel> {
+        let _ = (index, x, y);
+        vec![Self::Pixel::default(); width * height]
+    }
+}
+
+/// Bridge between [`JxlChunkedFrameInputSource`] callbacks and a [`ChunkedFrame`]
+///
+/// The encoder may call back from several threads at once, so the state is behind locks.
+/// Failures of the frame must not unwind through libjxl: they are kept, the encoder gets
+/// zeros, and [`Self::check`] reports them once the frame is added
+pub(crate) struct ChunkedSource<'a, C: ChunkedFrame> {
+    frame: Mutex<&'a mut C>,
+    num_channels: u32,
+    /// Buffers handed out to the encoder and not released yet
+    buffers: Mutex<Vec<Vec<C::Pixel>>>,
+    /// First failure of the frame
+    error: Mutex<Option<String>>,
+}
+
+impl<'a, C: ChunkedFrame> ChunkedSource<'a, C> {
+    pub(crate) fn new(frame: &'a mut C) -> Self {
+        Self {
+            num_channels: frame.num_channels(),
+            frame: Mutex::new(frame),
+            buffers: Mutex::new(vec![]),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Callbacks for the encoder. `self` must not move while the frame is added
+    pub(crate) fn as_raw(&self) -> JxlChunkedFrameInputSource {
+        JxlChunkedFrameInputSource {
+            opaque: std::ptr::from_ref(self).cast_mut().cast(),
+            get_color_channels_pixel_format: Self::get_color_channels_pixel_format,
+            get_color_channels_data_at: Self::get_color_channels_data_at,
+            get_extra_channel_pixel_format: Self::get_extra_channel_pixel_format,
+            get_extra_channel_data_at: Self::get_extra_channel_data_at,
+            release_buffer: Self::release_buffer,
+        }
+    }
+
+    /// Return the first failure of the frame
+    pub(crate) fn check(&self) -> Result<(), EncodeError> {
+        match lock(&self.error).take() {
+            Some(msg) => Err(EncodeError::ChunkedFrame(msg)),
+            None => Ok(()),
+        }
+    }
+
+    fn fail(&self, msg: String) {
+        lock(&self.error).get_or_insert(msg);
+    }
+
+    /// Request data from the frame, catching panics
+    fn request(&self, f: impl FnOnce(&mut C) -> Vec<C::Pixel>) -> Option<Vec<C::Pixel>> {
+        let mut frame = lock(&self.frame);
+        panic::catch_unwind(AssertUnwindSafe(|| f(&mut frame)))
+            .map_err(|payload| self.fail(panic_message(&*payload)))
+            .ok()
+    }
+
+    fn pixel_format(num_channels: u32) -> JxlPixelFormat {
+        JxlPixelFormat {
+            num_channels,
+            data_type: C::Pixel::pixel_type(),
+            endianness: JxlEndianness::Native,
+            align: 0,
+        }
+    }
+
+    /// Keep `data` alive until released, returning its pointer and row offset in bytes.
+    /// Missing data, or data of another length than `samples_per_row * height`, is replaced
+    /// by zeros
+    fn hand_out(
+        &self,
+        data: Option<Vec<C::Pixel>>,
+        samples_per_row: usize,
+        height: usize,
+        row_offset: *mut usize,
+    ) -> *const c_void {
+        let len = samples_per_row * height;
+        let data = match data {
+            Some(data) if data.len() == len => data,
+            data => {
+                if let Some(data) = data {
+                    self.fail(format!("Chunk of {} samples instead of {len}", data.len()));
+                }
+                vec![C::Pixel::default(); len]
+            }
+        };
+        unsafe { *row_offset = samples_per_row * std::mem::size_of::<C::Pixel>() };
+        let ptr = data.as_ptr().cast();
+        lock(&self.buffers).push(data);
+        ptr
+    }
+
+    extern "C-unwind" fn get_color_channels_pixel_format(
+        opaque: *mut c_void,
+        pixel_format: *mut JxlPixelFormat,
+    ) {
+        let this = unsafe { &*opaque.cast::<Self>() };
+        unsafe { *pixel_format = Self::pixel_format(this.num_channels) };
+    }
+
+    extern "C-unwind" fn get_color_channels_data_at(
+        opaque: *mut c_void,
+        xpos: usize,
+        ypos: usize,
+        xsize: usize,
+        ysize: usize,
+        row_offset: *mut usize,
+    ) -> *const c_void {
+        let this = unsafe { &*opaque.cast::<Self>() };
+        let data = this.request(|frame| frame.get_color_channel_data_at(xpos, ypos, xsize, ysize));
+        let samples_per_row = xsize * this.num_channels as usize;
+        this.hand_out(data, samples_per_row, ysize, row_offset)
+    }
+
+    extern "C-unwind" fn get_extra_channel_pixel_format(
+        _opaque: *mut c_void,
+        _ec_index: usize,
+        pixel_format: *mut JxlPixelFormat,
+    ) {
+        unsafe { *pixel_format = Self::pixel_format(1) };
+    }
+
+    extern "C-unwind" fn get_extra_channel_data_at(
+        opaque: *mut c_void,
+        ec_index: usize,
+        xpos: usize,
+        ypos: usize,
+        xsize: usize,
+        ysize: usize,
+        row_offset: *mut usize,
+    ) -> *const c_void {
+        let this = unsafe { &*opaque.cast::<Self>() };
+        let data = this
+            .request(|frame| frame.get_extra_channel_data_at(ec_index, xpos, ypos, xsize, ysize));
+        this.hand_out(data, xsize, ysize, row_offset)
+    }
+
+    extern "C-unwind" fn release_buffer(opaque: *mut c_void, buf: *const c_void) {
+        let this = unsafe { &*opaque.cast::<Self>() };
+        let mut buffers = lock(&this.buffers);
+        if let Some(i) = buffers
+            .iter()
+            .position(|b| b.as_ptr().cast::<c_void>() == buf)
+        {
+            buffers.swap_remove(i);
+        }
+    }
+}
+
+/// Lock, ignoring poisoning as panics are caught and reported
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,96 @@
+use std::mem::MaybeUninit;
+
+use jpegxl_sys::{
+    encoder::encode::JxlEncoderInitExtraChannelInfo,
+    metadata::codestream_header::{JxlExtraChannelInfo, JxlExtraChannelType},
+};
+
+/// Type of an extra channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtraChannelType {
+    /// Depth map
+    Depth,
+    /// Spot color, rendered with its color in linear RGB and its solidity in 0..1
+    SpotColor {
+        /// Linear RGB color
+        color: [f32; 3],
+        /// Solidity (opacity) of the spot color
+        solidity: f32,
+    },
+    /// Selection mask
+    SelectionMask,
+    /// K channel of CMYK, with the CMY channels as color channels and a CMYK
+    /// [`ColorEncoding::Icc`](super::ColorEncoding::Icc) profile
+    Black,
+    /// Thermal data
+    Thermal,
+    /// Channel that decoders can safely ignore
+    Optional,
+}
+
+/// An extra channel stored next to the color channels
+///
+/// Pass the pixels of each frame with [`EncoderFrame::extra_channel`](super::EncoderFrame::extra_channel),
+/// in the order of [`JxlEncoder::extra_channels`](super::JxlEncoder::extra_channels)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraChannel {
+    pub(crate) channel_type: ExtraChannelType,
+    pub(crate) name: Option<String>,
+    pub(crate) distance: Option<f32>,
+}
+
+impl ExtraChannel {
+    /// Create an extra channel of the type
+    #[must_use]
+    pub fn new(channel_type: ExtraChannelType) -> Self {
+        Self {
+            channel_type,
+            name: None,
+            distance: None,
+        }
+    }
+
+    /// Set the name of the channel
+    #[must_use]
+    pub fn name(mut self, value: impl Into<String>) -> Self {
+        self.name = Some(value.into());
+        self
+    }
+
+    /// Set the distance for lossy compression of the channel, 0 for lossless.
+    ///
+    /// Default: the distance of the color channels
+    #[must_use]
+    pub fn distance(mut self, value: f32) -> Self {
+        self.distance = Some(value);
+        self
+    }
+
+    pub(crate) fn info(&self, (bits, exp): (u32, u32)) -> JxlExtraChannelInfo {
+        use ExtraChannelType as T;
+
+        let channel_type = match self.channel_type {
+            T::Depth => JxlExtraChannelType::Depth,
+            T::SpotColor { .. } => JxlExtraChannelType::SpotColor,
+            T::SelectionMask => JxlExtraChannelType::SelectionMask,
+            T::Black => JxlExtraChannelType::Black,
+            T::Thermal => JxlExtraChannelType::Thermal,
+            T::Optional => JxlExtraChannelType::Optional,
+        };
+        let mut info = unsafe {
+            let mut info = MaybeUninit::uninit();
+            JxlEncoderInitExtraChannelInfo(channel_type, info.as_mut_ptr());
+            info.assume_init()
+        };
+        info.bits_per_sample = bits;
+        info.exponent_bits_per_sample = exp;
+        if let T::SpotColor {
+            color: [r, g, b],
+            solidity,
+        } = self.channel_type
+        {
+            info.spot_color = [r, g, b, solidity];
+        }
+        info
+    }
+}
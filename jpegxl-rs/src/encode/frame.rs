@@ -1,18 +1,33 @@
-use std::marker::PhantomData;
+use std::{ffi::CString, marker::PhantomData, mem::MaybeUninit};
 
-use jpegxl_sys::common::types::{JxlEndianness, JxlPixelFormat};
+use jpegxl_sys::{
+    common::types::{JxlDataType, JxlEndianness, JxlPixelFormat},
+    encoder::encode::JxlEncoderInitFrameHeader,
+    metadata::codestream_header::{JxlBlendMode, JxlFrameHeader},
+};
 
 use crate::{common::PixelType, EncodeError};
 
-use super::{EncoderResult, JxlEncoder};
+use super::{EncoderResult, EncoderSpeed, JxlEncoder};
+
+/// Blend mode of a layer
+pub type BlendMode = JxlBlendMode;
 
 /// A frame for the encoder, consisting of the pixels and its options
+#[derive(Clone)]
 #[allow(clippy::module_name_repetitions)]
 pub struct EncoderFrame<'data, T: PixelType> {
     pub(crate) data: &'data [T],
     num_channels: Option<u32>,
     endianness: Option<JxlEndianness>,
     align: Option<usize>,
+    pub(crate) header: Option<JxlFrameHeader>,
+    pub(crate) name: Option<String>,
+    pub(crate) alpha: Option<(JxlDataType, &'data [u8])>,
+    pub(crate) extra_channels: Vec<&'data [T]>,
+    pub(crate) quality: Option<f32>,
+    pub(crate) lossless: Option<bool>,
+    pub(crate) speed: Option<EncoderSpeed>,
 }
 
 impl<'data, T: PixelType> EncoderFrame<'data, T> {
@@ -25,6 +40,13 @@ impl<'data, T: PixelType> EncoderFrame<'data, T> {
             num_channels: None,
             endianness: None,
             align: None,
+            header: None,
+            name: None,
+            alpha: None,
+            extra_channels: vec![],
+            quality: None,
+            lossless: None,
+            speed: None,
         }
     }
 
@@ -52,6 +74,158 @@ impl<'data, T: PixelType> EncoderFrame<'data, T> {
         self
     }
 
+    /// Set the alpha channel from a separate plane, one sample per pixel, with the endianness
+    /// and the align of the frame. The sample type can differ from the color channels.
+    ///
+    /// The encoder must have `has_alpha` set, and the color channels must not include alpha
+    #[must_use]
+    pub fn alpha<A: PixelType>(mut self, data: &'data [A]) -> Self {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data.as_ptr().cast(), std::mem::size_of_val(data))
+        };
+        self.alpha = Some((A::pixel_type(), bytes));
+        self
+    }
+
+    /// Add the pixels of the next extra channel of the encoder, one sample per pixel,
+    /// with the endianness and the align of the frame
+    #[must_use]
+    pub fn extra_channel(mut self, data: &'data [T]) -> Self {
+        self.extra_channels.push(data);
+        self
+    }
+
+    /// Set how long the frame is shown, in ticks of the encoder's [`Animation`](super::Animation)
+    #[must_use]
+    pub fn duration(mut self, value: u32) -> Self {
+        self.header_mut().duration = value;
+        self
+    }
+
+    /// Set the SMPTE timecode of the frame in the form `0xHHMMSSFF`.
+    /// Only used if the animation has timecodes
+    #[must_use]
+    pub fn timecode(mut self, value: u32) -> Self {
+        self.header_mut().timecode = value;
+        self
+    }
+
+    /// Set the name of the frame, at most 1071 bytes
+    #[must_use]
+    pub fn name(mut self, value: impl Into<String>) -> Self {
+        self.name = Some(value.into());
+        self
+    }
+
+    /// Place the frame as a `width` x `height` layer at (`x0`, `y0`), which can be outside
+    /// the image. The pixels must have the layer dimensions
+    #[must_use]
+    pub fn crop(mut self, x0: i32, y0: i32, width: u32, height: u32) -> Self {
+        let info = &mut self.header_mut().layer_info;
+        info.have_crop = true.into();
+        info.crop_x0 = x0;
+        info.crop_y0 = y0;
+        info.xsize = width;
+        info.ysize = height;
+        self
+    }
+
+    /// Set how the frame is blended onto its source.
+    /// [`BlendMode::Blend`] and [`BlendMode::MULADD`] use the channel of [`Self::blend_alpha`]
+    #[must_use]
+    pub fn blend_mode(mut self, value: BlendMode) -> Self {
+        self.header_mut().layer_info.blend_info.blendmode = value;
+        self
+    }
+
+    /// Set the reference frame (0-3) the frame is blended onto
+    #[must_use]
+    pub fn blend_source(mut self, value: u32) -> Self {
+        self.header_mut().layer_info.blend_info.source = value;
+        self
+    }
+
+    /// Set the extra channel used as alpha by [`BlendMode::Blend`] and [`BlendMode::MULADD`].
+    /// The alpha channel is extra channel 0, followed by
+    /// [`JxlEncoder::extra_channels`](super::JxlEncoder::extra_channels)
+    ///
+    /// Default: 0
+    #[must_use]
+    pub fn blend_alpha(mut self, index: u32) -> Self {
+        self.header_mut().layer_info.blend_info.alpha = index;
+        self
+    }
+
+    /// Clamp the values to 0..1 for blending
+    ///
+    /// Default: `false`
+    #[must_use]
+    pub fn blend_clamp(mut self, value: bool) -> Self {
+        self.header_mut().layer_info.blend_info.clamp = value.into();
+        self
+    }
+
+    /// Save the frame after blending as reference frame `value` (0-3), so later frames can
+    /// use it as their blend source
+    #[must_use]
+    pub fn save_as_reference(mut self, value: u32) -> Self {
+        self.header_mut().layer_info.save_as_reference = value;
+        self
+    }
+
+    /// Set the quality of the frame, overriding [`JxlEncoder::quality`]
+    #[must_use]
+    pub fn quality(mut self, value: f32) -> Self {
+        self.quality = Some(value);
+        self
+    }
+
+    /// Set lossless for the frame, overriding [`JxlEncoder::lossless`]
+    #[must_use]
+    pub fn lossless(mut self, value: bool) -> Self {
+        self.lossless = Some(value);
+        self
+    }
+
+    /// Set the speed for the frame, overriding [`JxlEncoder::speed`]
+    #[must_use]
+    pub fn speed(mut self, value: EncoderSpeed) -> Self {
+        self.speed = Some(value);
+        self
+    }
+
+    /// Whether the frame overrides settings of the encoder
+    pub(crate) fn has_settings(&self) -> bool {
+        self.quality.is_some() || self.lossless.is_some() || self.speed.is_some()
+    }
+
+    /// Name as a C string
+    pub(crate) fn c_name(&self) -> Option<Result<CString, EncodeError>> {
+        self.name
+            .as_deref()
+            .map(|name| CString::new(name).map_err(|_| EncodeError::BadInput))
+    }
+
+    /// Frame header, initialized to the defaults on first access
+    pub(crate) fn header_mut(&mut self) -> &mut JxlFrameHeader {
+        self.header.get_or_insert_with(default_header)
+    }
+
+    /// Whether the color data is `width` x `height` pixels
+    pub(crate) fn has_size(&self, width: u32, height: u32) -> bool {
+        let format = self.pixel_format();
+        let row = width as usize * format.num_channels as usize * std::mem::size_of::<T>();
+        let stride = match format.align {
+            0 => row,
+            align => row.next_multiple_of(align),
+        };
+        let len = std::mem::size_of_val(self.data);
+        match height as usize {
+            0 => len == 0,
+            h => (stride * (h - 1) + row..=stride * h).contains(&len),
+        }
+    }
+
     pub(crate) fn pixel_format(&self) -> JxlPixelFormat {
         JxlPixelFormat {
             num_channels: self.num_channels.unwrap_or(3),
@@ -62,6 +236,52 @@ impl<'data, T: PixelType> EncoderFrame<'data, T> {
     }
 }
 
+/// Copy of an [`EncoderFrame`] owning its pixels, to add it later
+pub(crate) struct OwnedFrame<T: PixelType> {
+    options: EncoderFrame<'static, T>,
+    data: Vec<T>,
+    alpha: Option<(JxlDataType, Vec<u8>)>,
+    extra_channels: Vec<Vec<T>>,
+}
+
+impl<T: PixelType> OwnedFrame<T> {
+    pub(crate) fn new(frame: &EncoderFrame<T>) -> Self {
+        Self {
+            options: EncoderFrame {
+                data: &[],
+                num_channels: frame.num_channels,
+                endianness: frame.endianness,
+                align: frame.align,
+                header: frame.header.clone(),
+                name: frame.name.clone(),
+                alpha: None,
+                extra_channels: vec![],
+                quality: frame.quality,
+                lossless: frame.lossless,
+                speed: frame.speed,
+            },
+            data: frame.data.to_vec(),
+            alpha: frame
+                .alpha
+                .map(|(data_type, data)| (data_type, data.to_vec())),
+            extra_channels: frame.extra_channels.iter().map(|c| c.to_vec()).collect(),
+        }
+    }
+
+    /// The frame borrowing the copied pixels
+    pub(crate) fn as_frame(&self) -> EncoderFrame<'_, T> {
+        EncoderFrame {
+            data: &self.data,
+            alpha: self
+                .alpha
+                .as_ref()
+                .map(|(data_type, data)| (*data_type, data.as_slice())),
+            extra_channels: self.extra_channels.iter().map(Vec::as_slice).collect(),
+            ..self.options.clone()
+        }
+    }
+}
+
 /// A wrapper type for encoding multiple frames
 pub struct MultiFrames<'enc, 'prl, 'mm, U>(
     pub(crate) &'enc mut JxlEncoder<'prl, 'mm>,
@@ -95,3 +315,12 @@ impl<U: PixelType> MultiFrames<'_, '_, '_, U> {
         self.0.start_encoding()
     }
 }
+
+/// Frame header with the default values of libjxl
+pub(crate) fn default_header() -> JxlFrameHeader {
+    let mut header = MaybeUninit::uninit();
+    unsafe {
+        JxlEncoderInitFrameHeader(header.as_mut_ptr());
+        header.assume_init()
+    }
+}
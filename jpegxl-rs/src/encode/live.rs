@@ -0,0 +1,111 @@
+use std::io::Write;
+
+use jpegxl_sys::encoder::encode::{
+    JxlEncoderCloseBoxes, JxlEncoderCloseFrames, JxlEncoderFlushInput, JxlEncoderSetOutputProcessor,
+};
+
+use crate::{common::PixelType, EncodeError};
+
+use super::{
+    frame::OwnedFrame,
+    output::{BufferedOutput, OutputProcessor},
+    EncoderFrame, JxlEncoder,
+};
+
+/// Frame waiting to be added to the encoder
+type HeldFrame<'enc, 'prl, 'mm> =
+    Box<dyn FnOnce(&mut JxlEncoder<'prl, 'mm>) -> Result<(), EncodeError> + 'enc>;
+
+/// Encoding session adding frames over time, e.g. for live capture.
+///
+/// The output is written to the writer as soon as it is final. The encoder has to know
+/// which frame is the last before encoding it, so a copy of the most recent frame is held
+/// back until the next one is added or the session is finished.
+/// Dropping the session without [`finish`](Self::finish) abandons the image
+pub struct LiveSession<'enc, 'prl, 'mm, W: Write> {
+    encoder: &'enc mut JxlEncoder<'prl, 'mm>,
+    /// Boxed so its address stays valid for the encoder callbacks
+    processor: Option<Box<OutputProcessor<BufferedOutput<W>>>>,
+    held: Option<HeldFrame<'enc, 'prl, 'mm>>,
+}
+
+impl<'enc, 'prl, 'mm, W: Write> LiveSession<'enc, 'prl, 'mm, W> {
+    pub(crate) fn new(
+        encoder: &'enc mut JxlEncoder<'prl, 'mm>,
+        writer: W,
+    ) -> Result<Self, EncodeError> {
+        let mut processor = Box::new(OutputProcessor::new(BufferedOutput::new(writer)));
+        encoder.check_enc_status(unsafe {
+            JxlEncoderSetOutputProcessor(encoder.enc, processor.as_raw())
+        })?;
+        Ok(Self {
+            encoder,
+            processor: Some(processor),
+            held: None,
+        })
+    }
+
+    /// Hold back a copy of the frame, then encode the previous frame and write the output
+    /// that became final
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if the internal encoder fails to encode the previous frame
+    /// or writing fails. Once writing failed, every later call returns the error
+    pub fn add_frame<T: PixelType>(&mut self, frame: &EncoderFrame<T>) -> Result<(), EncodeError> {
+        self.check_error()?;
+        let frame = OwnedFrame::new(frame);
+        let previous = self.held.replace(Box::new(move |encoder: &mut JxlEncoder| {
+            encoder.add_frame(&frame.as_frame())
+        }));
+        match previous {
+            Some(add) => {
+                add(self.encoder)?;
+                self.flush()
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Close the input and write the rest of the image
+    ///
+    /// # Errors
+    /// Return [`EncodeError`] if the internal encoder fails to encode or writing fails
+    pub fn finish(mut self) -> Result<(), EncodeError> {
+        // The held frame is encoded as the last one, since the frames are closed before
+        // flushing it
+        let added = self.held.take().map_or(Ok(()), |add| add(self.encoder));
+        let result = added.and_then(|()| {
+            unsafe {
+                JxlEncoderCloseFrames(self.encoder.enc);
+                JxlEncoderCloseBoxes(self.encoder.enc);
+            }
+            self.flush()
+        });
+        self.encoder.reset();
+
+        let written = self.processor.take().map_or(Ok(()), |p| p.finish());
+        result?;
+        Ok(written?)
+    }
+
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        let status = unsafe { JxlEncoderFlushInput(self.encoder.enc) };
+        self.check_error()?;
+        self.encoder.check_enc_status(status)
+    }
+
+    // Return the writing error, if any
+    fn check_error(&self) -> Result<(), EncodeError> {
+        let processor = self.processor.as_ref().ok_or(EncodeError::ApiUsage)?;
+        processor.error().map_or(Ok(()), |err| Err(err.into()))
+    }
+}
+
+impl<W: Write> Drop for LiveSession<'_, '_, '_, W> {
+    fn drop(&mut self) {
+        if self.processor.is_some() {
+            // The encoder must not call the processor once it is dropped
+            self.encoder.reset();
+        }
+    }
+}
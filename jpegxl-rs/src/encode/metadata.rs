@@ -1,5 +1,7 @@
 use jpegxl_sys::common::types::JxlBoxType;
 
+use crate::decode::Orientation;
+
 /// Metadata box
 pub enum Metadata<'d> {
     /// EXIF
@@ -17,8 +19,103 @@ pub enum Metadata<'d> {
 }
 
 impl Metadata<'_> {
+    /// Read the orientation tag of an [`Exif`](Self::Exif) box, e.g. to set
+    /// [`JxlEncoder::orientation`](super::JxlEncoder::orientation)
+    ///
+    /// Return `None` for other boxes, or if the tag is missing or invalid
+    #[must_use]
+    pub fn exif_orientation(&self) -> Option<Orientation> {
+        use Orientation as O;
+
+        const ORIENTATION_TAG: u16 = 0x0112;
+
+        let Self::Exif(data) = self else {
+            return None;
+        };
+        let offset = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+        let tiff = data.get(usize::try_from(offset).ok()?.checked_add(4)?..)?;
+        let big_endian = match tiff.get(..2)? {
+            b"MM" => true,
+            b"II" => false,
+            _ => return None,
+        };
+        let u16_at = |pos: usize| {
+            let bytes = tiff.get(pos..pos.checked_add(2)?)?.try_into().ok()?;
+            Some(if big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            })
+        };
+        let u32_at = |pos: usize| {
+            let bytes = tiff.get(pos..pos.checked_add(4)?)?.try_into().ok()?;
+            Some(if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            })
+        };
+
+        // Entries of 12 bytes follow the entry count
+        let ifd = usize::try_from(u32_at(4)?).ok()?;
+        let entries = ifd.checked_add(2)?;
+        let value = (0..usize::from(u16_at(ifd)?))
+            .map_while(|i| entries.checked_add(i * 12))
+            .find(|&entry| u16_at(entry) == Some(ORIENTATION_TAG))
+            .and_then(|entry| u16_at(entry.checked_add(8)?))?;
+
+        Some(match value {
+            1 => O::Identity,
+            2 => O::FlipHorizontal,
+            3 => O::Rotate180,
+            4 => O::FlipVertical,
+            5 => O::Transpose,
+            6 => O::Rotate90Cw,
+            7 => O::AntiTranspose,
+            8 => O::Rotate90Ccw,
+            _ => return None,
+        })
+    }
+
     #[must_use]
     pub(crate) fn box_type(t: [u8; 4]) -> JxlBoxType {
         JxlBoxType(unsafe { std::mem::transmute::<[u8; 4], [std::ffi::c_char; 4]>(t) })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn exif_orientation() {
+        // Tiff header offset, little endian header, IFD with the orientation tag
+        let mut exif = vec![0, 0, 0, 0];
+        exif.extend_from_slice(b"II\x2a\0\x08\0\0\0");
+        exif.extend_from_slice(&[1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            Metadata::Exif(&exif).exif_orientation(),
+            Some(Orientation::Rotate90Cw)
+        );
+
+        // Big endian
+        let mut exif = vec![0, 0, 0, 0];
+        exif.extend_from_slice(b"MM\0\x2a\0\0\0\x08");
+        exif.extend_from_slice(&[0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 3, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            Metadata::Exif(&exif).exif_orientation(),
+            Some(Orientation::Rotate180)
+        );
+
+        assert_eq!(Metadata::Exif(&exif[..12]).exif_orientation(), None);
+
+        // Out of range offsets
+        assert_eq!(Metadata::Exif(&[0xff; 4]).exif_orientation(), None);
+        let mut exif = vec![0, 0, 0, 0];
+        exif.extend_from_slice(b"MM\0\x2a\xff\xff\xff\xff");
+        assert_eq!(Metadata::Exif(&exif).exif_orientation(), None);
+        assert_eq!(Metadata::Xmp(&exif).exif_orientation(), None);
+    }
+}
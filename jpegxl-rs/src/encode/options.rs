@@ -1,6 +1,13 @@
 use std::mem::MaybeUninit;
 
-use jpegxl_sys::{color::color_encoding::JxlColorEncoding, encoder::encode as api};
+use jpegxl_sys::{
+    color::color_encoding::JxlColorEncoding,
+    common::types::{JxlBitDepth, JxlBitDepthType},
+    encoder::encode::{self as api, JxlEncoderFrameSettingId},
+    metadata::codestream_header::JxlAnimationHeader,
+};
+
+use crate::EncodeError;
 
 /// Encoding speed
 #[derive(Debug, Clone, Copy, Default)]
@@ -24,8 +31,96 @@ pub enum EncoderSpeed {
     Kitten,
     /// 9
     Tortoise,
-    /// Slowest, 10
+    /// 10
     Glacier,
+    /// Slowest, 11. Requires `expert_options`
+    TectonicPlate,
+}
+
+/// Feature level of the codestream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CodestreamLevel {
+    /// Level 5 if the image allows it, otherwise 10
+    #[default]
+    Auto = -1,
+    /// Supported by all decoders, e.g. up to 16 bits per color channel and 268435456 pixels
+    Level5 = 5,
+    /// Removes most limits of level 5, but may be less widely supported
+    Level10 = 10,
+}
+
+/// Size budget of an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeTarget {
+    /// At most this many bytes
+    Bytes(usize),
+    /// At most this many bits per pixel
+    BitsPerPixel(f32),
+}
+
+impl SizeTarget {
+    /// Budget in bytes for an image of the dimensions
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn max_bytes(self, width: u32, height: u32) -> usize {
+        match self {
+            Self::Bytes(bytes) => bytes,
+            Self::BitsPerPixel(bpp) => {
+                (f64::from(bpp) * f64::from(width) * f64::from(height) / 8.0) as usize
+            }
+        }
+    }
+}
+
+/// Bit depth of the encoded samples, independent of the pixel type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    /// Unsigned integers of 1 to 16 bits
+    Uint {
+        /// Bits per sample
+        bits: u32,
+        /// Whether input samples are in `0..2^bits` instead of the full range of the pixel
+        /// type, e.g. `0..4096` for 12-bit samples in `u16`
+        raw_range: bool,
+    },
+    /// Floats
+    Float {
+        /// Bits per sample
+        bits: u32,
+        /// Exponent bits per sample
+        exponent_bits: u32,
+    },
+}
+
+impl BitDepth {
+    /// Bits and exponent bits per sample
+    pub(crate) fn bits(self) -> Result<(u32, u32), EncodeError> {
+        match self {
+            Self::Uint { bits, .. } if (1..=16).contains(&bits) => Ok((bits, 0)),
+            Self::Float {
+                bits,
+                exponent_bits,
+            } if (2..=8).contains(&exponent_bits) && bits <= 32 && bits > exponent_bits + 1 => {
+                Ok((bits, exponent_bits))
+            }
+            _ => Err(EncodeError::NotSupported),
+        }
+    }
+
+    /// Interpretation of the input samples
+    pub(crate) fn input(self) -> JxlBitDepth {
+        let (r#type, bits_per_sample) = match self {
+            Self::Uint {
+                bits,
+                raw_range: true,
+            } => (JxlBitDepthType::FromCodestream, bits),
+            _ => (JxlBitDepthType::FromPixelFormat, 0),
+        };
+        JxlBitDepth {
+            r#type,
+            bits_per_sample,
+            exponent_bits_per_sample: 0,
+        }
+    }
 }
 
 /// Encoding color profile
@@ -41,16 +136,33 @@ pub enum ColorEncoding {
     LinearSrgbLuma,
     /// Custom
     Custom(JxlColorEncoding),
+    /// ICC profile, e.g. from a camera or a print workflow.
+    ///
+    /// Set `uses_original_profile` to store the pixels in the profile's color space instead of
+    /// converting them to XYB. A CMYK profile also needs it, with the CMY channels as color
+    /// channels and K as an [`ExtraChannelType::Black`](super::ExtraChannelType::Black) channel
+    Icc(Vec<u8>),
 }
 
-impl From<&ColorEncoding> for JxlColorEncoding {
-    fn from(val: &ColorEncoding) -> Self {
-        use ColorEncoding::{Custom, LinearSrgb, LinearSrgbLuma, Srgb, SrgbLuma};
+impl ColorEncoding {
+    /// Whether the encoding has only a luma channel
+    pub(crate) fn is_gray(&self) -> bool {
+        match self {
+            Self::SrgbLuma | Self::LinearSrgbLuma => true,
+            // Data color space in the profile header
+            Self::Icc(profile) => profile.get(16..20) == Some(b"GRAY"),
+            _ => false,
+        }
+    }
+
+    /// The enumerated encoding, or the ICC profile which has no enumerated equivalent
+    pub(crate) fn to_jxl(&self) -> Result<JxlColorEncoding, &[u8]> {
+        use ColorEncoding::{Custom, Icc, LinearSrgb, LinearSrgbLuma, Srgb, SrgbLuma};
 
         let mut color_encoding = MaybeUninit::uninit();
 
         unsafe {
-            match val {
+            match self {
                 Srgb => api::JxlColorEncodingSetToSRGB(color_encoding.as_mut_ptr(), false.into()),
                 LinearSrgb => {
                     api::JxlColorEncodingSetToLinearSRGB(color_encoding.as_mut_ptr(), false.into());
@@ -62,10 +174,284 @@ impl From<&ColorEncoding> for JxlColorEncoding {
                     api::JxlColorEncodingSetToLinearSRGB(color_encoding.as_mut_ptr(), true.into());
                 }
                 Custom(e) => {
-                    return e.clone();
+                    return Ok(e.clone());
                 }
+                Icc(profile) => return Err(profile),
+            }
+            Ok(color_encoding.assume_init())
+        }
+    }
+
+    /// Whether the encoding is an ICC profile of CMYK data
+    pub(crate) fn is_cmyk(&self) -> bool {
+        matches!(self, Self::Icc(profile) if profile.get(16..20) == Some(b"CMYK"))
+    }
+
+    /// Check the header of an ICC profile
+    pub(crate) fn validate_icc(profile: &[u8]) -> Result<(), EncodeError> {
+        let size = profile
+            .get(..4)
+            .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]) as usize);
+        if profile.len() < 128 || &profile[36..40] != b"acsp" || size != Some(profile.len()) {
+            return Err(EncodeError::InvalidIccProfile);
+        }
+        Ok(())
+    }
+}
+
+/// Animation settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Animation {
+    /// Numerator of ticks per second, the time unit of frame durations
+    pub tps_numerator: u32,
+    /// Denominator of ticks per second
+    pub tps_denominator: u32,
+    /// Number of loops, or 0 to repeat infinitely
+    pub num_loops: u32,
+    /// Whether frames have SMPTE timecodes
+    pub have_timecodes: bool,
+}
+
+impl Default for Animation {
+    /// Millisecond ticks, repeating infinitely
+    fn default() -> Self {
+        Self {
+            tps_numerator: 1000,
+            tps_denominator: 1,
+            num_loops: 0,
+            have_timecodes: false,
+        }
+    }
+}
+
+impl From<&Animation> for JxlAnimationHeader {
+    fn from(val: &Animation) -> Self {
+        Self {
+            tps_numerator: val.tps_numerator,
+            tps_denominator: val.tps_denominator,
+            num_loops: val.num_loops,
+            have_timecodes: val.have_timecodes.into(),
+        }
+    }
+}
+
+/// Downsampling factor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resampling {
+    /// No downsampling
+    X1 = 1,
+    /// 2x2 downsampling
+    X2 = 2,
+    /// 4x4 downsampling
+    X4 = 4,
+    /// 8x8 downsampling
+    X8 = 8,
+}
+
+/// How decoders upsample a downsampled image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpsamplingMode {
+    /// Good for photographic images
+    #[default]
+    Default = -1,
+    /// Nearest neighbor, good for pixel art
+    NearestNeighbor = 0,
+    /// Same as nearest neighbor for 2x, diamond-shaped dots for 4x and 8x
+    PixelDots = 1,
+}
+
+/// Input at a reduced resolution, upsampled by decoders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Downsampled {
+    /// Factor the input is reduced by. Frames are `ceil(width / factor)` x
+    /// `ceil(height / factor)` pixels, with the full size given to the encoder
+    pub factor: Resampling,
+    /// Upsampling used by decoders
+    pub upsampling: UpsamplingMode,
+}
+
+/// Encoding mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingMode {
+    /// DCT based, e.g. for photographic images
+    VarDct,
+    /// Modular, e.g. for lossless images
+    Modular,
+}
+
+/// Order of the 256x256 groups in the codestream, for progressive rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupOrder {
+    /// Scanline order
+    Scanline,
+    /// Center first, around (`x`, `y`) or the middle of the image if `None`
+    CenterFirst {
+        /// Horizontal position of the center
+        x: Option<u32>,
+        /// Vertical position of the center
+        y: Option<u32>,
+    },
+}
+
+/// Frame settings applied on every encode.
+///
+/// `None` leaves the choice to the encoder
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameSettings {
+    /// Downsample the image before compression, and upsample it in the decoder
+    pub resampling: Option<Resampling>,
+    /// Downsample the extra channels
+    pub extra_channel_resampling: Option<Resampling>,
+    /// Edge preserving filter strength, 0 to 3
+    pub epf: Option<u8>,
+    /// Enable the gaborish filter
+    pub gaborish: Option<bool>,
+    /// Enable patches generation
+    pub patches: Option<bool>,
+    /// Enable dots generation
+    pub dots: Option<bool>,
+    /// Enable adaptive noise generation. Prefer `photon_noise_iso`
+    pub noise: Option<bool>,
+    /// Add film grain noise of the ISO value, e.g. 100 for low and 3200 for a lot of noise
+    pub photon_noise_iso: Option<f32>,
+    /// Order of the groups in the codestream
+    pub group_order: Option<GroupOrder>,
+    /// Force `VarDCT` or modular encoding
+    pub mode: Option<EncodingMode>,
+}
+
+/// Raw frame setting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FrameOption {
+    Int(JxlEncoderFrameSettingId, i64),
+    Float(JxlEncoderFrameSettingId, f32),
+}
+
+impl FrameOption {
+    pub(crate) fn id(self) -> JxlEncoderFrameSettingId {
+        match self {
+            Self::Int(id, _) | Self::Float(id, _) => id,
+        }
+    }
+}
+
+impl FrameSettings {
+    /// Check the values before giving them to the encoder
+    pub(crate) fn validate(&self) -> Result<(), EncodeError> {
+        let center = match self.group_order {
+            Some(GroupOrder::CenterFirst { x, y }) => [x, y],
+            _ => [None, None],
+        };
+        if self.epf.is_some_and(|v| v > 3)
+            || self.photon_noise_iso.is_some_and(|v| v.is_nan() || v < 0.0)
+            || center
+                .into_iter()
+                .flatten()
+                .any(|v| i32::try_from(v).is_err())
+        {
+            return Err(EncodeError::NotSupported);
+        }
+        Ok(())
+    }
+
+    /// Raw settings to give to the encoder
+    pub(crate) fn options(&self) -> Vec<FrameOption> {
+        use FrameOption::{Float, Int};
+        use JxlEncoderFrameSettingId as Id;
+
+        let flag = |id, value: Option<bool>| value.map(|v| Int(id, i64::from(v)));
+        let mut options = vec![
+            self.resampling.map(|v| Int(Id::Resampling, v as i64)),
+            self.extra_channel_resampling
+                .map(|v| Int(Id::ExtraChannelResampling, v as i64)),
+            self.epf.map(|v| Int(Id::Epf, i64::from(v))),
+            flag(Id::Gaborish, self.gaborish),
+            flag(Id::Patches, self.patches),
+            flag(Id::Dots, self.dots),
+            flag(Id::Noise, self.noise),
+            self.photon_noise_iso.map(|v| Float(Id::PhotonNoise, v)),
+            flag(Id::Modular, self.mode.map(|v| v == EncodingMode::Modular)),
+        ];
+        match self.group_order {
+            Some(GroupOrder::Scanline) => options.push(Some(Int(Id::GroupOrder, 0))),
+            Some(GroupOrder::CenterFirst { x, y }) => {
+                let center = |v: Option<u32>| v.map_or(-1, i64::from);
+                options.extend([
+                    Some(Int(Id::GroupOrder, 1)),
+                    Some(Int(Id::GroupOrderCenterX, center(x))),
+                    Some(Int(Id::GroupOrderCenterY, center(y))),
+                ]);
             }
-            color_encoding.assume_init()
+            None => (),
+        }
+        options.into_iter().flatten().collect()
+    }
+}
+
+/// Options for recompressing JPEG with [`JxlEncoder::encode_jpeg`](super::JxlEncoder::encode_jpeg)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct JpegOptions {
+    /// Store the data to reconstruct the original JPEG file.
+    /// Without it the output is smaller, but only the pixels can be decoded
+    pub store_reconstruction: bool,
+    /// Keep Exif metadata. Required when storing the reconstruction data
+    pub keep_exif: bool,
+    /// Keep XMP metadata. Required when storing the reconstruction data
+    pub keep_xmp: bool,
+    /// Keep JUMBF metadata
+    pub keep_jumbf: bool,
+    /// Enable chroma from luma, `None` for the encoder default
+    pub cfl: Option<bool>,
+    /// Compress the metadata boxes with brotli, `None` for the encoder default
+    pub compress_boxes: Option<bool>,
+    /// Reconstruct the JPEG after encoding and check it is identical to the source.
+    /// Requires `store_reconstruction`
+    pub verify: bool,
+}
+
+impl Default for JpegOptions {
+    /// Store the reconstruction data and keep all metadata, without verifying
+    fn default() -> Self {
+        Self {
+            store_reconstruction: true,
+            keep_exif: true,
+            keep_xmp: true,
+            keep_jumbf: true,
+            cfl: None,
+            compress_boxes: None,
+            verify: false,
+        }
+    }
+}
+
+impl JpegOptions {
+    /// Check the options are consistent
+    pub(crate) fn validate(self) -> Result<(), EncodeError> {
+        if self.store_reconstruction && !(self.keep_exif && self.keep_xmp)
+            || self.verify && !self.store_reconstruction
+        {
+            return Err(EncodeError::ApiUsage);
         }
+        Ok(())
+    }
+
+    /// Raw settings to give to the encoder
+    pub(crate) fn options(self) -> Vec<FrameOption> {
+        use FrameOption::Int;
+        use JxlEncoderFrameSettingId as Id;
+
+        let flag = |id, value: bool| Some(Int(id, i64::from(value)));
+        [
+            flag(Id::JpegKeepExif, self.keep_exif),
+            flag(Id::JpegKeepXmp, self.keep_xmp),
+            flag(Id::JpegKeepJumbf, self.keep_jumbf),
+            self.cfl.and_then(|v| flag(Id::JpegReconCfl, v)),
+            self.compress_boxes
+                .and_then(|v| flag(Id::JpegCompressBoxes, v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
     }
 }
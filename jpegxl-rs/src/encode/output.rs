@@ -0,0 +1,281 @@
+use std::{
+    any::Any,
+    ffi::c_void,
+    io::{self, Seek, SeekFrom, Write},
+    panic::{self, AssertUnwindSafe},
+    ptr::null_mut,
+};
+
+use jpegxl_sys::encoder::encode::JxlEncoderOutputProcessor;
+
+/// Destination of an [`OutputProcessor`]
+pub(crate) trait Output {
+    /// Write `data` at the current position
+    fn write(&mut self, data: &[u8]) -> io::Result<()>;
+    /// Move the current position
+    fn seek(&mut self, position: usize) -> io::Result<()>;
+    /// Data before `position` will not be changed anymore
+    fn finalize(&mut self, position: usize) -> io::Result<()>;
+    /// Write everything left after encoding
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Write directly to a seekable writer, relative to its position at the start
+pub(crate) struct SeekOutput<W: Write + Seek> {
+    writer: W,
+    start: u64,
+    position: usize,
+    end: usize,
+}
+
+impl<W: Write + Seek> SeekOutput<W> {
+    pub(crate) fn new(mut writer: W) -> io::Result<Self> {
+        let start = writer.stream_position()?;
+        Ok(Self {
+            writer,
+            start,
+            position: 0,
+            end: 0,
+        })
+    }
+}
+
+impl<W: Write + Seek> Output for SeekOutput<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.position += data.len();
+        self.end = self.end.max(self.position);
+        Ok(())
+    }
+
+    fn seek(&mut self, position: usize) -> io::Result<()> {
+        self.writer
+            .seek(SeekFrom::Start(self.start + position as u64))?;
+        self.position = position;
+        Ok(())
+    }
+
+    fn finalize(&mut self, _position: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.seek(self.end)?;
+        self.writer.flush()
+    }
+}
+
+/// Write to a non-seekable writer, keeping the data after the finalized position in memory
+pub(crate) struct BufferedOutput<W: Write> {
+    writer: W,
+    pending: Vec<u8>,
+    finalized: usize,
+    position: usize,
+}
+
+impl<W: Write> BufferedOutput<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: vec![],
+            finalized: 0,
+            position: 0,
+        }
+    }
+}
+
+impl<W: Write> Output for BufferedOutput<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let offset = self.position - self.finalized;
+        let end = offset + data.len();
+        if end > self.pending.len() {
+            self.pending.resize(end, 0);
+        }
+        self.pending[offset..end].copy_from_slice(data);
+        self.position += data.len();
+        Ok(())
+    }
+
+    fn seek(&mut self, position: usize) -> io::Result<()> {
+        if position < self.finalized {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the finalized position",
+            ));
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    fn finalize(&mut self, position: usize) -> io::Result<()> {
+        let len = (position - self.finalized).min(self.pending.len());
+        self.writer.write_all(&self.pending[..len])?;
+        self.pending.drain(..len);
+        self.finalized += len;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.pending)?;
+        self.pending.clear();
+        self.writer.flush()
+    }
+}
+
+/// Bridge between [`JxlEncoderOutputProcessor`] callbacks and an [`Output`]
+pub(crate) struct OutputProcessor<O: Output> {
+    output: O,
+    buffer: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+impl<O: Output> OutputProcessor<O> {
+    pub(crate) fn new(output: O) -> Self {
+        Self {
+            output,
+            buffer: vec![],
+            error: None,
+        }
+    }
+
+    /// Callbacks for the encoder. `self` must not move until the encoder is reset
+    pub(crate) fn as_raw(&mut self) -> JxlEncoderOutputProcessor {
+        JxlEncoderOutputProcessor {
+            opaque: std::ptr::from_mut(self).cast(),
+            get_buffer: Self::get_buffer,
+            release_buffer: Self::release_buffer,
+            seek: Some(Self::seek),
+            set_finalized_position: Self::set_finalized_position,
+        }
+    }
+
+    /// Write the remaining data, or return the first error during encoding
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.output.finish(),
+        }
+    }
+
+    /// A copy of the first error during encoding, which is kept for [`Self::finish`]
+    pub(crate) fn error(&self) -> Option<io::Error> {
+        self.error
+            .as_ref()
+            .map(|err| io::Error::new(err.kind(), err.to_string()))
+    }
+
+    // Keep the first error. A panic of the output must not unwind through libjxl,
+    // so it is kept as an error too
+    fn record(&mut self, f: impl FnOnce(&mut O) -> io::Result<()>) {
+        if self.error.is_none() {
+            let output = &mut self.output;
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(output)))
+                .unwrap_or_else(|payload| Err(io::Error::other(panic_message(&*payload))));
+            if let Err(err) = result {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn to_usize(position: u64) -> io::Result<usize> {
+        usize::try_from(position)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Output is too large"))
+    }
+
+    extern "C-unwind" fn get_buffer(opaque: *mut c_void, size: *mut usize) -> *mut c_void {
+        let this = unsafe { &mut *opaque.cast::<Self>() };
+        if this.error.is_some() {
+            // Ask the encoder to stop
+            unsafe { *size = 0 };
+            return null_mut();
+        }
+
+        this.buffer.resize(unsafe { *size }, 0);
+        this.buffer.as_mut_ptr().cast()
+    }
+
+    extern "C-unwind" fn release_buffer(opaque: *mut c_void, written_bytes: usize) {
+        let this = unsafe { &mut *opaque.cast::<Self>() };
+        let buffer = std::mem::take(&mut this.buffer);
+        this.record(|output| output.write(&buffer[..written_bytes]));
+        this.buffer = buffer;
+    }
+
+    extern "C-unwind" fn seek(opaque: *mut c_void, position: u64) {
+        let this = unsafe { &mut *opaque.cast::<Self>() };
+        this.record(|output| output.seek(Self::to_usize(position)?));
+    }
+
+    extern "C-unwind" fn set_finalized_position(opaque: *mut c_void, finalized_position: u64) {
+        let this = unsafe { &mut *opaque.cast::<Self>() };
+        this.record(|output| output.finalize(Self::to_usize(finalized_position)?));
+    }
+}
+
+/// Message of a caught panic
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(ToString::to_string)
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .map_or_else(
+            || "Panic during encoding".to_string(),
+            |msg| format!("Panic during encoding: {msg}"),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+    use testresult::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn seek_output() -> TestResult {
+        let mut cursor = Cursor::new(vec![9]);
+        cursor.set_position(1);
+        let mut output = SeekOutput::new(&mut cursor)?;
+        output.write(&[0, 0, 3])?;
+        output.seek(0)?;
+        output.write(&[1, 2])?;
+        output.finish()?;
+
+        assert_eq!(cursor.position(), 4);
+        assert_eq!(cursor.into_inner(), vec![9, 1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn buffered_output() -> TestResult {
+        let mut data = vec![];
+        let mut output = BufferedOutput::new(&mut data);
+        output.write(&[0, 0, 3])?;
+        output.finalize(0)?;
+        output.seek(0)?;
+        output.write(&[1, 2])?;
+        output.finalize(2)?;
+        assert_eq!(output.pending, vec![3]);
+        assert!(output.seek(1).is_err());
+        output.seek(3)?;
+        output.write(&[4])?;
+        output.finish()?;
+
+        assert_eq!(data, vec![1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn panicking_output() {
+        let mut processor = OutputProcessor::new(BufferedOutput::new(vec![]));
+        processor.record(|_| panic!("writer failed"));
+        processor.record(|output| output.write(&[1]));
+
+        let err = processor.error().expect("panic not recorded");
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "Panic during encoding: writer failed");
+        assert!(processor.finish().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use std::ops::AddAssign;
+
+use jpegxl_sys::encoder::stats::{
+    JxlEncoderStats, JxlEncoderStatsCreate, JxlEncoderStatsDestroy, JxlEncoderStatsGet,
+    JxlEncoderStatsKey,
+};
+
+use crate::EncodeError;
+
+/// Where the bits of an encoded image go, and which blocks were used
+///
+/// Add the stats of several images together with `+=`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderStats {
+    /// Bits of the headers
+    pub header_bits: usize,
+    /// Bits of the table of contents
+    pub toc_bits: usize,
+    /// Bits of the patch dictionary
+    pub dictionary_bits: usize,
+    /// Bits of the splines
+    pub splines_bits: usize,
+    /// Bits of the noise parameters
+    pub noise_bits: usize,
+    /// Bits of the quantization tables
+    pub quant_bits: usize,
+    /// Bits of the modular MA trees
+    pub modular_tree_bits: usize,
+    /// Bits of the global modular data
+    pub modular_global_bits: usize,
+    /// Bits of the DC coefficients
+    pub dc_bits: usize,
+    /// Bits of the modular DC groups
+    pub modular_dc_group_bits: usize,
+    /// Bits of the control fields
+    pub control_fields_bits: usize,
+    /// Bits of the coefficient orders
+    pub coef_order_bits: usize,
+    /// Bits of the AC histograms
+    pub ac_histogram_bits: usize,
+    /// Bits of the AC coefficients
+    pub ac_bits: usize,
+    /// Bits of the modular AC groups
+    pub modular_ac_group_bits: usize,
+    /// Number of `DCT2`, `DCT4`, `DCT4x2` and identity blocks
+    pub num_small_blocks: usize,
+    /// Number of `DCT4x8` blocks
+    pub num_dct4x8_blocks: usize,
+    /// Number of `AFV` blocks
+    pub num_afv_blocks: usize,
+    /// Number of `DCT8` blocks
+    pub num_dct8_blocks: usize,
+    /// Number of `DCT8x16` and `DCT8x32` blocks
+    pub num_dct8x32_blocks: usize,
+    /// Number of `DCT16` blocks
+    pub num_dct16_blocks: usize,
+    /// Number of `DCT16x32` blocks
+    pub num_dct16x32_blocks: usize,
+    /// Number of `DCT32` blocks
+    pub num_dct32_blocks: usize,
+    /// Number of `DCT32x64` blocks
+    pub num_dct32x64_blocks: usize,
+    /// Number of `DCT64` blocks
+    pub num_dct64_blocks: usize,
+    /// Number of butteraugli iterations
+    pub num_butteraugli_iters: usize,
+}
+
+impl EncoderStats {
+    fn fields_mut(&mut self) -> [(JxlEncoderStatsKey, &mut usize); 26] {
+        use JxlEncoderStatsKey as K;
+
+        [
+            (K::HeaderBits, &mut self.header_bits),
+            (K::TocBits, &mut self.toc_bits),
+            (K::DictionaryBits, &mut self.dictionary_bits),
+            (K::SplinesBits, &mut self.splines_bits),
+            (K::NoiseBits, &mut self.noise_bits),
+            (K::QuantBits, &mut self.quant_bits),
+            (K::ModularTreeBits, &mut self.modular_tree_bits),
+            (K::ModularGlobalBits, &mut self.modular_global_bits),
+            (K::DcBits, &mut self.dc_bits),
+            (K::ModularDcGroupBits, &mut self.modular_dc_group_bits),
+            (K::ControlFieldsBits, &mut self.control_fields_bits),
+            (K::CoefOrderBits, &mut self.coef_order_bits),
+            (K::AcHistogramBits, &mut self.ac_histogram_bits),
+            (K::AcBits, &mut self.ac_bits),
+            (K::ModularAcGroupBits, &mut self.modular_ac_group_bits),
+            (K::NumSmallBlocks, &mut self.num_small_blocks),
+            (K::NumDct4x8Blocks, &mut self.num_dct4x8_blocks),
+            (K::NumAfvBlocks, &mut self.num_afv_blocks),
+            (K::NumDct8Blocks, &mut self.num_dct8_blocks),
+            (K::NumDct8x32Blocks, &mut self.num_dct8x32_blocks),
+            (K::NumDct16Blocks, &mut self.num_dct16_blocks),
+            (K::NumDct16x32Blocks, &mut self.num_dct16x32_blocks),
+            (K::NumDct32Blocks, &mut self.num_dct32_blocks),
+            (K::NumDct32x64Blocks, &mut self.num_dct32x64_blocks),
+            (K::NumDct64Blocks, &mut self.num_dct64_blocks),
+            (K::NumButteraugliIters, &mut self.num_butteraugli_iters),
+        ]
+    }
+
+    fn fields(&self) -> [usize; 26] {
+        let mut stats = *self;
+        stats.fields_mut().map(|(_, v)| *v)
+    }
+
+    /// Total number of bits
+    #[must_use]
+    pub fn total_bits(&self) -> usize {
+        [
+            self.header_bits,
+            self.toc_bits,
+            self.dictionary_bits,
+            self.splines_bits,
+            self.noise_bits,
+            self.quant_bits,
+            self.modular_tree_bits,
+            self.modular_global_bits,
+            self.dc_bits,
+            self.modular_dc_group_bits,
+            self.control_fields_bits,
+            self.coef_order_bits,
+            self.ac_histogram_bits,
+            self.ac_bits,
+            self.modular_ac_group_bits,
+        ]
+        .iter()
+        .sum()
+    }
+}
+
+impl AddAssign<&Self> for EncoderStats {
+    fn add_assign(&mut self, rhs: &Self) {
+        for ((_, v), other) in self.fields_mut().into_iter().zip(rhs.fields()) {
+            *v += other;
+        }
+    }
+}
+
+/// Owned [`JxlEncoderStats`], collecting the stats during an encode
+pub(crate) struct StatsCollector(*mut JxlEncoderStats);
+
+impl StatsCollector {
+    pub(crate) fn new() -> Result<Self, EncodeError> {
+        let ptr = unsafe { JxlEncoderStatsCreate() };
+        if ptr.is_null() {
+            return Err(EncodeError::OutOfMemory);
+        }
+        Ok(Self(ptr))
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut JxlEncoderStats {
+        self.0
+    }
+
+    pub(crate) fn stats(&self) -> EncoderStats {
+        let mut stats = EncoderStats::default();
+        for (key, v) in stats.fields_mut() {
+            *v = unsafe { JxlEncoderStatsGet(self.0, key) };
+        }
+        stats
+    }
+}
+
+impl Drop for StatsCollector {
+    fn drop(&mut self) {
+        unsafe { JxlEncoderStatsDestroy(self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let mut stats = EncoderStats {
+            header_bits: 8,
+            ac_bits: 100,
+            num_dct8_blocks: 3,
+            ..Default::default()
+        };
+        stats += &EncoderStats {
+            header_bits: 16,
+            num_dct8_blocks: 1,
+            ..Default::default()
+        };
+        assert_eq!(stats.header_bits, 24);
+        assert_eq!(stats.num_dct8_blocks, 4);
+        assert_eq!(stats.total_bits(), 124);
+    }
+}
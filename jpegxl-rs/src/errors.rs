@@ -80,6 +80,22 @@ pub enum EncodeError {
     /// Input is invalid (e.g. corrupt JPEG file or ICC profile)
     #[error("Input is invalid")]
     BadInput,
+    /// The ICC profile is malformed or cannot be used
+    #[error("Invalid ICC profile")]
+    InvalidIccProfile,
+    /// The image needs a higher codestream level than the one set,
+    /// `None` if no level supports it
+    #[error("The image needs a higher codestream level than the one set: {required:?}")]
+    CodestreamLevel {
+        /// Level required by the image
+        required: Option<u32>,
+    },
+    /// Even the lowest quality does not fit in the size budget
+    #[error("Cannot encode the image within the size budget")]
+    SizeBudget,
+    /// The JPEG reconstructed from the output differs from the source
+    #[error("The reconstructed JPEG differs from the source")]
+    JpegVerification,
     /// The encoder API is used in an incorrect way. In this case,
     /// a debug build of libjxl should output a specific error message
     #[error("The encoder API is used in an incorrect way")]
@@ -87,6 +103,13 @@ pub enum EncodeError {
     /// Unknown status
     #[error("Unknown status: `{0:?}`")]
     UnknownStatus(JxlEncoderError),
+    /// Writing the output failed
+    #[error("Cannot write the output: {0}")]
+    Io(#[from] std::io::Error),
+    /// A [`ChunkedFrame`](crate::encode::ChunkedFrame) panicked or returned a chunk of
+    /// the wrong size
+    #[error("The chunked frame failed: {0}")]
+    ChunkedFrame(String),
 }
 
 /// Error mapping from underlying C const to [`DecodeError`] enum
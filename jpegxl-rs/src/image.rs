@@ -17,17 +17,360 @@
 
 //! `image` crate integration
 
-use std::mem::MaybeUninit;
+use std::{
+    io::{Read, Write},
+    mem::MaybeUninit,
+    num::NonZeroU32,
+    ops::Not,
+    time::Duration,
+};
 
-use image::{DynamicImage, ImageBuffer};
-use jpegxl_sys::common::types::{JxlDataType, JxlPixelFormat};
+use image::{
+    error::{DecodingError, EncodingError, ImageFormatHint, UnsupportedError},
+    metadata::LoopCount,
+    AnimationDecoder, ColorType, Delay, DynamicImage, ExtendedColorType, Frame, Frames,
+    ImageBuffer, ImageDecoder, ImageEncoder, ImageError, ImageResult, RgbaImage,
+};
+use jpegxl_sys::common::types::{JxlBool, JxlDataType, JxlPixelFormat};
 
 use crate::{
     common::PixelType,
-    decode::{JxlDecoder, Metadata},
-    DecodeError,
+    decode::{AnimationHeader, BasicInfo, JxlDecoder, Metadata, PixelFormat},
+    decoder_builder,
+    encode::{
+        Animation, BlendMode, ColorEncoding, EncoderFrame, ExtraChannel, ExtraChannelType,
+        JxlEncoder,
+    },
+    DecodeError, EncodeError, Endianness,
 };
 
+const CODESTREAM_SIGNATURE: &[u8] = &[0xff, 0x0a];
+const CONTAINER_SIGNATURE: &[u8] = &[
+    0x00, 0x00, 0x00, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+];
+
+impl From<DecodeError> for ImageError {
+    fn from(err: DecodeError) -> Self {
+        ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name("JPEG XL".to_string()),
+            err,
+        ))
+    }
+}
+
+impl From<EncodeError> for ImageError {
+    fn from(err: EncodeError) -> Self {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Name("JPEG XL".to_string()),
+            err,
+        ))
+    }
+}
+
+/// Register JPEG XL to `image`'s decoding hooks for the `jxl` extension and signatures,
+/// so [`image::ImageReader`] and [`image::load_from_memory`] can open JPEG XL images
+/// with a default [`JxlDecoder`].
+///
+/// Return `false` if a hook for `jxl` is already registered.
+#[allow(clippy::must_use_candidate)]
+pub fn register_hooks() -> bool {
+    let registered = image::hooks::register_decoding_hook(
+        "jxl".into(),
+        Box::new(|reader| {
+            Ok(Box::new(JxlReader::new(
+                decoder_builder().build()?,
+                reader,
+            )?))
+        }),
+    );
+    if registered {
+        image::hooks::register_format_detection_hook("jxl".into(), CODESTREAM_SIGNATURE, None);
+        image::hooks::register_format_detection_hook("jxl".into(), CONTAINER_SIGNATURE, None);
+    }
+    registered
+}
+
+/// JPEG XL reader implementing [`ImageDecoder`]
+///
+/// Pixels are decoded as 8-bit or 16-bit integers depending on the bit depth of the image,
+/// or as 32-bit floats for floating point images. Grayscale floating point images are expanded
+/// to RGB, since `image` has no floating point luma type.
+pub struct JxlReader<'pr, 'mm> {
+    decoder: JxlDecoder<'pr, 'mm>,
+    data: Vec<u8>,
+    info: BasicInfo,
+}
+
+impl<'pr, 'mm> JxlReader<'pr, 'mm> {
+    /// Read the whole JPEG XL image from `reader` and parse its header
+    ///
+    /// # Errors
+    /// Return an [`ImageError`] when reading fails or the header is invalid
+    pub fn new<R: Read>(decoder: JxlDecoder<'pr, 'mm>, mut reader: R) -> ImageResult<Self> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Ok(Self::from_bytes(decoder, data)?)
+    }
+
+    /// Parse the header of a JPEG XL image in memory
+    ///
+    /// # Errors
+    /// Return a [`DecodeError`] when the header is invalid
+    pub fn from_bytes(decoder: JxlDecoder<'pr, 'mm>, data: Vec<u8>) -> Result<Self, DecodeError> {
+        let (info, _) = decoder.decode_header(&data, false)?;
+        Ok(Self {
+            decoder,
+            data,
+            info,
+        })
+    }
+
+    /// Whether the decoder applies the orientation to the pixels
+    fn reorients(&self) -> bool {
+        self.decoder.skip_reorientation != Some(true)
+    }
+
+    fn data_type(&self) -> JxlDataType {
+        match self.color_type() {
+            ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => {
+                JxlDataType::Uint8
+            }
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+                JxlDataType::Uint16
+            }
+            _ => JxlDataType::Float,
+        }
+    }
+}
+
+impl ImageDecoder for JxlReader<'_, '_> {
+    fn dimensions(&self) -> (u32, u32) {
+        use crate::decode::Orientation as O;
+
+        let BasicInfo { xsize, ysize, .. } = self.info;
+        match self.info.orientation {
+            O::Transpose | O::Rotate90Cw | O::AntiTranspose | O::Rotate90Ccw
+                if self.reorients() =>
+            {
+                (ysize, xsize)
+            }
+            _ => (xsize, ysize),
+        }
+    }
+
+    fn color_type(&self) -> ColorType {
+        let alpha = self.info.alpha_bits > 0;
+        let gray = self.info.num_color_channels == 1;
+        match (
+            self.info.exponent_bits_per_sample,
+            self.info.bits_per_sample,
+        ) {
+            (0, ..=8) => match (gray, alpha) {
+                (true, false) => ColorType::L8,
+                (true, true) => ColorType::La8,
+                (false, false) => ColorType::Rgb8,
+                (false, true) => ColorType::Rgba8,
+            },
+            (0, _) => match (gray, alpha) {
+                (true, false) => ColorType::L16,
+                (true, true) => ColorType::La16,
+                (false, false) => ColorType::Rgb16,
+                (false, true) => ColorType::Rgba16,
+            },
+            _ if alpha => ColorType::Rgba32F,
+            _ => ColorType::Rgb32F,
+        }
+    }
+
+    fn icc_profile(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        Ok(self.decoder.decode_header(&self.data, true)?.1)
+    }
+
+    fn exif_metadata(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        // The box starts with the offset of the TIFF header
+        Ok(self
+            .decoder
+            .decode_box(&self.data, *b"Exif")?
+            .and_then(|exif| {
+                let offset = u32::from_be_bytes(exif.get(..4)?.try_into().ok()?);
+                let start = usize::try_from(offset).ok()?.checked_add(4)?;
+                Some(exif.get(start..)?.to_vec())
+            }))
+    }
+
+    fn xmp_metadata(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        Ok(self.decoder.decode_box(&self.data, *b"xml ")?)
+    }
+
+    fn orientation(&mut self) -> ImageResult<image::metadata::Orientation> {
+        use image::metadata::Orientation;
+
+        Ok(if self.reorients() {
+            Orientation::NoTransforms
+        } else {
+            Orientation::from_exif(self.info.orientation as u8).unwrap_or(Orientation::NoTransforms)
+        })
+    }
+
+    fn read_image(mut self, buf: &mut [u8]) -> ImageResult<()> {
+        let data_type = self.data_type();
+        self.decoder.pixel_format = Some(PixelFormat {
+            num_channels: u32::from(self.color_type().channel_count()),
+            endianness: Endianness::Native,
+            align: 0,
+        });
+
+        let mut buffer = vec![];
+        let mut pixel_format = MaybeUninit::uninit();
+        self.decoder.decode_internal(
+            &self.data,
+            Some(data_type),
+            false,
+            None,
+            pixel_format.as_mut_ptr(),
+            &mut buffer,
+        )?;
+
+        if buffer.len() != buf.len() {
+            return Err(DecodeError::InternalError("Unexpected output buffer size").into());
+        }
+        buf.copy_from_slice(&buffer);
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+impl JxlReader<'_, '_> {
+    /// Write all frames, coalesced, as an APNG, keeping the frame durations
+    /// and the loop count
+    ///
+    /// # Errors
+    /// Return an [`ImageError`] when decoding or writing fails
+    #[cfg(feature = "apng")]
+    pub fn write_apng<W: Write>(self, writer: W) -> ImageResult<()> {
+        fn png_error(err: png::EncodingError) -> ImageError {
+            ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Exact(image::ImageFormat::Png),
+                err,
+            ))
+        }
+
+        let (width, height) = self.dimensions();
+        let num_plays = match self.loop_count() {
+            LoopCount::Infinite => 0,
+            LoopCount::Finite(n) => n.get(),
+        };
+        let frames = self.into_frames().collect_frames()?;
+
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(u32::try_from(frames.len()).unwrap_or(u32::MAX), num_plays)
+            .map_err(png_error)?;
+
+        let mut writer = encoder.write_header().map_err(png_error)?;
+        for frame in &frames {
+            let (numer, denom) = to_apng_delay(frame.delay());
+            writer.set_frame_delay(numer, denom).map_err(png_error)?;
+            writer
+                .write_image_data(frame.buffer().as_raw())
+                .map_err(png_error)?;
+        }
+        writer.finish().map_err(png_error)
+    }
+
+    /// Write all frames, coalesced, as a GIF, keeping the frame durations
+    /// and the loop count
+    ///
+    /// # Errors
+    /// Return an [`ImageError`] when decoding or writing fails
+    #[cfg(feature = "gif")]
+    pub fn write_gif<W: Write>(self, writer: W) -> ImageResult<()> {
+        use image::codecs::gif::{GifEncoder, Repeat};
+
+        let repeat = match self.loop_count() {
+            LoopCount::Infinite => Repeat::Infinite,
+            LoopCount::Finite(n) => Repeat::Finite(u16::try_from(n.get()).unwrap_or(u16::MAX)),
+        };
+
+        let mut encoder = GifEncoder::new(writer);
+        encoder.set_repeat(repeat)?;
+        encoder.try_encode_frames(self.into_frames())
+    }
+}
+
+/// Convert a [`Delay`] to an APNG delay in seconds, exact when it fits in `u16`
+#[cfg(feature = "apng")]
+fn to_apng_delay(delay: Delay) -> (u16, u16) {
+    let (numer, denom) = delay.numer_denom_ms();
+    let g = gcd(numer, 1000);
+    let (n, d) = (numer / g, u64::from(denom) * u64::from(1000 / g));
+    if let (Ok(n), Ok(d)) = (u16::try_from(n), u16::try_from(d)) {
+        return (n, d);
+    }
+
+    let ms = u64::from(numer) / u64::from(denom);
+    u16::try_from(ms).map_or_else(
+        |_| (u16::try_from(ms / 1000).unwrap_or(u16::MAX), 1),
+        |ms| (ms, 1000),
+    )
+}
+
+impl<'a, 'pr: 'a, 'mm: 'a> AnimationDecoder<'a> for JxlReader<'pr, 'mm> {
+    /// Decode all frames, coalesced to the image dimensions, as RGBA
+    fn into_frames(self) -> Frames<'a> {
+        let (width, height) = self.dimensions();
+        let animation = self.info.animation.clone();
+        let frames = match self
+            .decoder
+            .decode_frames(&self.data, JxlDataType::Uint8, 4)
+        {
+            Ok(frames) => frames,
+            Err(err) => return Frames::new(Box::new(std::iter::once(Err(err.into())))),
+        };
+
+        Frames::new(Box::new(frames.into_iter().map(move |(header, data)| {
+            let buffer = RgbaImage::from_raw(width, height, data)
+                .ok_or(DecodeError::InternalError("Unexpected output buffer size"))?;
+            Ok(Frame::from_parts(
+                buffer,
+                0,
+                0,
+                to_delay(header.duration, &animation),
+            ))
+        })))
+    }
+
+    fn loop_count(&self) -> LoopCount {
+        if self.info.have_animation == JxlBool::False {
+            return LoopCount::Finite(NonZeroU32::MIN);
+        }
+        NonZeroU32::new(self.info.animation.num_loops)
+            .map_or(LoopCount::Infinite, LoopCount::Finite)
+    }
+}
+
+/// Convert a frame duration in ticks to a [`Delay`]
+fn to_delay(duration: u32, animation: &AnimationHeader) -> Delay {
+    if animation.tps_numerator == 0 {
+        return Delay::from_numer_denom_ms(0, 1);
+    }
+
+    let ms = u64::from(duration) * 1000 * u64::from(animation.tps_denominator);
+    u32::try_from(ms).map_or_else(
+        |_| {
+            Delay::from_saturating_duration(Duration::from_millis(
+                ms / u64::from(animation.tps_numerator),
+            ))
+        },
+        |ms| Delay::from_numer_denom_ms(ms, animation.tps_numerator),
+    )
+}
+
 /// Extension trait for [`JxlDecoder`]
 pub trait ToDynamic {
     /// Decode the JPEG XL image to a [`DynamicImage`]
@@ -85,6 +428,379 @@ impl ToDynamic for JxlDecoder<'_, '_> {
     }
 }
 
+/// Extension trait for [`JxlEncoder`]
+pub trait FromDynamic {
+    /// Encode a [`DynamicImage`] to JPEG XL.
+    ///
+    /// The channel count, pixel type and bit depth follow the color type of the image.
+    /// Grayscale images use the luma variant of the color encoding, and `has_alpha`
+    /// is set from the color type.
+    ///
+    /// # Errors
+    /// Return an [`EncodeError`] when internal encoding fails
+    fn encode_dynamic(&mut self, image: &DynamicImage) -> Result<Vec<u8>, EncodeError>;
+
+    /// Encode the frames of an [`AnimationDecoder`], e.g. GIF, APNG or WebP, to an
+    /// animated JPEG XL.
+    ///
+    /// Frame delays and the loop count are kept. Frames smaller than the canvas are
+    /// placed at their offsets and alpha-blended over the previous frame, as `image`
+    /// already applies the disposal of the source. Set `lossless` on the encoder for
+    /// a pixel-exact conversion; the original profile is then used.
+    ///
+    /// # Errors
+    /// Return an [`ImageError`] when decoding the frames or internal encoding fails
+    fn encode_animation<'a, D: AnimationDecoder<'a>>(&mut self, decoder: D)
+        -> ImageResult<Vec<u8>>;
+}
+
+impl FromDynamic for JxlEncoder<'_, '_> {
+    fn encode_dynamic(&mut self, image: &DynamicImage) -> Result<Vec<u8>, EncodeError> {
+        encode_raw(
+            self,
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color().into(),
+            None,
+        )
+    }
+
+    fn encode_animation<'a, D: AnimationDecoder<'a>>(
+        &mut self,
+        decoder: D,
+    ) -> ImageResult<Vec<u8>> {
+        let num_loops = match decoder.loop_count() {
+            LoopCount::Infinite => 0,
+            LoopCount::Finite(n) => n.get(),
+        };
+        let frames = decoder.into_frames().collect_frames()?;
+        let (width, height) = frames.iter().fold((0, 0), |(w, h), frame| {
+            (
+                w.max(frame.left() + frame.buffer().width()),
+                h.max(frame.top() + frame.buffer().height()),
+            )
+        });
+        let (tps, durations) = to_ticks(&frames.iter().map(Frame::delay).collect::<Vec<_>>());
+
+        let layers: Vec<_> = frames
+            .iter()
+            .zip(durations)
+            .map(|(frame, duration)| {
+                let buffer = frame.buffer();
+                // Keep every frame as the base of the next one
+                let layer = EncoderFrame::new(buffer.as_raw())
+                    .num_channels(4)
+                    .duration(duration)
+                    .save_as_reference(1);
+                if buffer.dimensions() == (width, height) {
+                    layer
+                } else {
+                    layer
+                        .crop(
+                            frame.left().cast_signed(),
+                            frame.top().cast_signed(),
+                            buffer.width(),
+                            buffer.height(),
+                        )
+                        .blend_mode(BlendMode::Blend)
+                        .blend_source(1)
+                }
+            })
+            .collect();
+
+        let has_alpha = std::mem::replace(&mut self.has_alpha, true);
+        let uses_original_profile = self.uses_original_profile;
+        self.uses_original_profile |= self.lossless == Some(true);
+        let color_encoding = self.color_encoding.take();
+        self.color_encoding = color_encoding_for(color_encoding.as_ref(), false);
+        let animation = self.animation.replace(Animation {
+            tps_numerator: tps,
+            tps_denominator: 1,
+            num_loops,
+            have_timecodes: false,
+        });
+
+        let result = self.encode_frames(&layers, width, height);
+
+        self.has_alpha = has_alpha;
+        self.uses_original_profile = uses_original_profile;
+        self.color_encoding = color_encoding;
+        self.animation = animation;
+        Ok(result?)
+    }
+}
+
+/// Ticks per second and the durations in ticks of `delays`,
+/// exact unless the common denominator of the delays overflows
+fn to_ticks(delays: &[Delay]) -> (u32, Vec<u32>) {
+    let denom = delays
+        .iter()
+        .try_fold(1u32, |acc, delay| {
+            let (_, denom) = delay.numer_denom_ms();
+            let lcm = acc.checked_mul(denom / gcd(acc, denom))?;
+            lcm.checked_mul(1000).map(|_| lcm)
+        })
+        .unwrap_or(1);
+    let durations = delays
+        .iter()
+        .map(|delay| {
+            let (numer, d) = delay.numer_denom_ms();
+            u32::try_from(u64::from(numer) * u64::from(denom) / u64::from(d)).unwrap_or(u32::MAX)
+        })
+        .collect();
+
+    (1000 * denom, durations)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Writer implementing [`ImageEncoder`] with a [`JxlEncoder`]
+///
+/// Sub-byte color types are stored with their original bit depth.
+/// `A8` is stored as gray with alpha, with the luma at its maximum.
+/// `Rgb5x1` is read as a little-endian `u16` with red in the high bits and blue in the low
+/// bits, like the 15-bit TGA images of `image`, and stored with 5 bits per sample.
+///
+/// CMYK needs a CMYK ICC profile, set with [`ImageEncoder::set_icc_profile`] or as the
+/// [`JxlEncoder::color_encoding`]. The CMY samples are the color channels and K is an
+/// [`ExtraChannelType::Black`] channel, inverted to the JPEG XL convention of 0 for full ink.
+///
+/// Unknown color types are not supported.
+pub struct JxlWriter<'enc, 'prl, 'mm, W: Write> {
+    encoder: &'enc mut JxlEncoder<'prl, 'mm>,
+    writer: W,
+    icc_profile: Option<Vec<u8>>,
+}
+
+impl<'enc, 'prl, 'mm, W: Write> JxlWriter<'enc, 'prl, 'mm, W> {
+    /// Create a writer encoding with `encoder` and writing to `writer`
+    pub fn new(encoder: &'enc mut JxlEncoder<'prl, 'mm>, writer: W) -> Self {
+        Self {
+            encoder,
+            writer,
+            icc_profile: None,
+        }
+    }
+}
+
+impl<W: Write> ImageEncoder for JxlWriter<'_, '_, '_, W> {
+    fn write_image(
+        mut self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ExtendedColorType,
+    ) -> ImageResult<()> {
+        let data = encode_raw(
+            self.encoder,
+            buf,
+            width,
+            height,
+            color_type,
+            self.icc_profile,
+        )?;
+        self.writer.write_all(&data)?;
+        Ok(())
+    }
+
+    fn set_icc_profile(&mut self, icc_profile: Vec<u8>) -> Result<(), UnsupportedError> {
+        self.icc_profile = Some(icc_profile);
+        Ok(())
+    }
+}
+
+fn encode_raw(
+    encoder: &mut JxlEncoder,
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ExtendedColorType,
+    icc_profile: Option<Vec<u8>>,
+) -> Result<Vec<u8>, EncodeError> {
+    use ExtendedColorType as C;
+
+    let (num_channels, bits) = match color_type {
+        C::A8 => (2, 8),
+        C::Rgb5x1 => (3, 5),
+        C::Cmyk8 | C::Cmyk16 => (3, color_type.bits_per_pixel() / 4),
+        C::L1 | C::L2 | C::L4 | C::L8 | C::L16 => (1, color_type.bits_per_pixel()),
+        C::La1 | C::La2 | C::La4 | C::La8 | C::La16 => (2, color_type.bits_per_pixel() / 2),
+        C::Rgb1 | C::Rgb2 | C::Rgb4 | C::Rgb8 | C::Bgr8 | C::Rgb16 | C::Rgb32F => {
+            (3, color_type.bits_per_pixel() / 3)
+        }
+        C::Rgba1 | C::Rgba2 | C::Rgba4 | C::Rgba8 | C::Bgra8 | C::Rgba16 | C::Rgba32F => {
+            (4, color_type.bits_per_pixel() / 4)
+        }
+        _ => return Err(EncodeError::NotSupported),
+    };
+    let bits = u32::from(bits);
+
+    let color_encoding = match icc_profile {
+        Some(profile) => Some(ColorEncoding::Icc(profile)),
+        None => color_encoding_for(encoder.color_encoding.as_ref(), num_channels <= 2),
+    };
+    let cmyk = matches!(color_type, C::Cmyk8 | C::Cmyk16);
+    if cmyk && !color_encoding.as_ref().is_some_and(ColorEncoding::is_cmyk) {
+        return Err(EncodeError::ApiUsage);
+    }
+
+    let has_alpha = std::mem::replace(&mut encoder.has_alpha, num_channels % 2 == 0);
+    let color_encoding = std::mem::replace(&mut encoder.color_encoding, color_encoding);
+    let extra_channels = cmyk.then(|| {
+        std::mem::replace(
+            &mut encoder.extra_channels,
+            vec![ExtraChannel::new(ExtraChannelType::Black)],
+        )
+    });
+
+    let result = encode_pixels(
+        encoder,
+        buf,
+        width,
+        height,
+        color_type,
+        (num_channels, bits),
+    );
+
+    encoder.has_alpha = has_alpha;
+    encoder.color_encoding = color_encoding;
+    if let Some(extra_channels) = extra_channels {
+        encoder.extra_channels = extra_channels;
+    }
+    result
+}
+
+/// Encode `buf` as a frame of `num_channels` color and alpha channels
+fn encode_pixels(
+    encoder: &mut JxlEncoder,
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ExtendedColorType,
+    (num_channels, bits): (u32, u32),
+) -> Result<Vec<u8>, EncodeError> {
+    use ExtendedColorType as C;
+
+    match color_type {
+        C::Rgb32F | C::Rgba32F => {
+            let data: Vec<f32> = buf
+                .chunks_exact(4)
+                .map(|v| f32::from_ne_bytes([v[0], v[1], v[2], v[3]]))
+                .collect();
+            let frame = EncoderFrame::new(&data).num_channels(num_channels);
+            encoder.encode_frame_with_bits(&frame, width, height, (32, 8))
+        }
+        C::L16 | C::La16 | C::Rgb16 | C::Rgba16 => {
+            let data: Vec<u16> = buf
+                .chunks_exact(2)
+                .map(|v| u16::from_ne_bytes([v[0], v[1]]))
+                .collect();
+            let frame = EncoderFrame::new(&data).num_channels(num_channels);
+            encoder.encode_frame_with_bits(&frame, width, height, (16, 0))
+        }
+        C::A8 => {
+            let data: Vec<u8> = buf.iter().flat_map(|&alpha| [u8::MAX, alpha]).collect();
+            let frame = EncoderFrame::new(&data).num_channels(num_channels);
+            encoder.encode_frame_with_bits(&frame, width, height, (8, 0))
+        }
+        C::Rgb5x1 => {
+            let data: Vec<u8> = buf
+                .chunks_exact(2)
+                .flat_map(|v| {
+                    let pixel = u16::from_le_bytes([v[0], v[1]]);
+                    [10, 5, 0].map(|shift| {
+                        let sample = (pixel >> shift & 0x1f) as u8;
+                        sample << 3 | sample >> 2
+                    })
+                })
+                .collect();
+            let frame = EncoderFrame::new(&data).num_channels(num_channels);
+            encoder.encode_frame_with_bits(&frame, width, height, (5, 0))
+        }
+        C::Cmyk8 => {
+            let (cmy, black) = split_cmyk(buf);
+            let frame = EncoderFrame::new(&cmy).extra_channel(&black);
+            encoder.encode_frame_with_bits(&frame, width, height, (8, 0))
+        }
+        C::Cmyk16 => {
+            let data: Vec<u16> = buf
+                .chunks_exact(2)
+                .map(|v| u16::from_ne_bytes([v[0], v[1]]))
+                .collect();
+            let (cmy, black) = split_cmyk(&data);
+            let frame = EncoderFrame::new(&cmy).extra_channel(&black);
+            encoder.encode_frame_with_bits(&frame, width, height, (16, 0))
+        }
+        C::Bgr8 | C::Bgra8 => {
+            let mut data = buf.to_vec();
+            for pixel in data.chunks_exact_mut(num_channels as usize) {
+                pixel.swap(0, 2);
+            }
+            let frame = EncoderFrame::new(&data).num_channels(num_channels);
+            encoder.encode_frame_with_bits(&frame, width, height, (8, 0))
+        }
+        _ if bits < 8 => {
+            let data = unpack_bits(buf, width, height, num_channels, bits);
+            let frame = EncoderFrame::new(&data).num_channels(num_channels);
+            encoder.encode_frame_with_bits(&frame, width, height, (bits, 0))
+        }
+        _ => {
+            let frame = EncoderFrame::new(buf).num_channels(num_channels);
+            encoder.encode_frame_with_bits(&frame, width, height, (8, 0))
+        }
+    }
+}
+
+/// Split CMYK samples into CMY and K, inverted so that 0 is full ink
+fn split_cmyk<T: Copy + Not<Output = T>>(samples: &[T]) -> (Vec<T>, Vec<T>) {
+    let pixels = samples.chunks_exact(4);
+    let cmy = pixels.clone().flat_map(|v| [!v[0], !v[1], !v[2]]).collect();
+    let black = pixels.map(|v| !v[3]).collect();
+    (cmy, black)
+}
+
+/// Switch between the luma and the RGB variant of the color encoding
+fn color_encoding_for(color_encoding: Option<&ColorEncoding>, gray: bool) -> Option<ColorEncoding> {
+    use ColorEncoding::{LinearSrgb, LinearSrgbLuma, Srgb, SrgbLuma};
+
+    match (color_encoding, gray) {
+        (None | Some(Srgb), true) => Some(SrgbLuma),
+        (Some(LinearSrgb), true) => Some(LinearSrgbLuma),
+        (Some(SrgbLuma), false) => Some(Srgb),
+        (Some(LinearSrgbLuma), false) => Some(LinearSrgb),
+        (e, _) => e.cloned(),
+    }
+}
+
+/// Unpack rows of 1, 2 or 4-bit samples, scaling them to the full `u8` range
+fn unpack_bits(buf: &[u8], width: u32, height: u32, num_channels: u32, bits: u32) -> Vec<u8> {
+    let samples = (width * num_channels) as usize;
+    let pitch = (samples * bits as usize).div_ceil(8);
+    if pitch == 0 {
+        return vec![];
+    }
+
+    let max = u8::MAX >> (8 - bits);
+    let scale = u8::MAX / max;
+    buf.chunks_exact(pitch)
+        .take(height as usize)
+        .flat_map(|row| {
+            (0..samples).map(move |i| {
+                let bit = i * bits as usize;
+                let shift = 8 - bits as usize - bit % 8;
+                (row[bit / 8] >> shift & max) * scale
+            })
+        })
+        .collect()
+}
+
 fn to_image(
     Metadata { width, height, .. }: Metadata,
     pixel_format: &JxlPixelFormat,
@@ -135,9 +851,8 @@ fn to_image(
 mod tests {
     use super::*;
     use crate::{
-        decode::PixelFormat,
-        decoder_builder,
-        tests::{SAMPLE_JXL, SAMPLE_JXL_GRAY, SAMPLE_PNG},
+        encoder_builder,
+        tests::{cmyk_profile, SAMPLE_JXL, SAMPLE_JXL_GRAY, SAMPLE_PNG},
         ThreadsRunner,
     };
 
@@ -171,6 +886,308 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn reader() -> TestResult {
+        let reader = JxlReader::new(decoder_builder().build()?, SAMPLE_JXL)?;
+        assert_eq!(reader.color_type(), ColorType::Rgba16);
+
+        let img = DynamicImage::from_decoder(reader)?;
+        let sample_png = image::load_from_memory_with_format(SAMPLE_PNG, image::ImageFormat::Png)?;
+        assert_eq!(img.to_rgba16(), sample_png.to_rgba16());
+
+        let mut reader = JxlReader::new(decoder_builder().build()?, SAMPLE_JXL_GRAY)?;
+        assert_eq!(reader.color_type(), ColorType::L16);
+        assert!(reader.icc_profile()?.is_some());
+        assert!(reader.exif_metadata()?.is_none());
+        assert_eq!(
+            reader.orientation()?,
+            image::metadata::Orientation::NoTransforms
+        );
+        DynamicImage::from_decoder(reader)?;
+
+        assert!(JxlReader::new(decoder_builder().build()?, &[0u8; 64][..]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn exif() -> TestResult {
+        let tiff = [b'M', b'M', 0, 42, 0, 0, 0, 8, 0, 0];
+        let exif = [&[0, 0, 0, 0][..], &tiff].concat();
+
+        let sample = image::load_from_memory_with_format(SAMPLE_PNG, image::ImageFormat::Png)?;
+        let mut encoder = encoder_builder().build()?;
+        encoder.add_metadata(&crate::encode::Metadata::Exif(&exif), false)?;
+        let data = encoder.encode_dynamic(&sample)?;
+
+        let mut reader = JxlReader::new(decoder_builder().build()?, &data[..])?;
+        assert_eq!(reader.exif_metadata()?, Some(tiff.to_vec()));
+
+        let mut encoder = encoder_builder().build()?;
+        encoder.add_metadata(&crate::encode::Metadata::Exif(&[0, 0, 0, 16, 0]), false)?;
+        let data = encoder.encode_dynamic(&sample)?;
+        let mut reader = JxlReader::new(decoder_builder().build()?, &data[..])?;
+        assert!(reader.exif_metadata()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn hooks() -> TestResult {
+        register_hooks();
+        assert!(!register_hooks());
+
+        let img = image::ImageReader::new(std::io::Cursor::new(SAMPLE_JXL))
+            .with_guessed_format()?
+            .decode()?;
+        let sample_png = image::load_from_memory_with_format(SAMPLE_PNG, image::ImageFormat::Png)?;
+        assert_eq!(img.to_rgba16(), sample_png.to_rgba16());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn animation() -> TestResult {
+        let reader = JxlReader::new(decoder_builder().build()?, SAMPLE_JXL)?;
+        assert!(matches!(
+            reader.loop_count(),
+            LoopCount::Finite(NonZeroU32::MIN)
+        ));
+        let frames = reader.into_frames().collect_frames()?;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].delay(), Delay::from_numer_denom_ms(0, 1));
+
+        let sample_png = image::load_from_memory_with_format(SAMPLE_PNG, image::ImageFormat::Png)?;
+        assert_eq!(frames[0].buffer(), &sample_png.to_rgba8());
+
+        let animation = AnimationHeader {
+            tps_numerator: 100,
+            tps_denominator: 1,
+            num_loops: 0,
+            have_timecodes: JxlBool::False,
+        };
+        assert_eq!(to_delay(5, &animation), Delay::from_numer_denom_ms(50, 1));
+        let animation = AnimationHeader {
+            tps_numerator: 1,
+            tps_denominator: 1000,
+            ..animation
+        };
+        assert_eq!(
+            to_delay(u32::MAX, &animation),
+            Delay::from_saturating_duration(Duration::from_secs(u64::from(u32::MAX) * 1000))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn animation_frames() -> TestResult {
+        let red = [255u8, 0, 0, 255].repeat(16);
+        let blue = [0u8, 0, 255, 255].repeat(16);
+        let mut encoder = encoder_builder()
+            .has_alpha(true)
+            .lossless(true)
+            .uses_original_profile(true)
+            .animation(crate::encode::Animation {
+                tps_numerator: 100,
+                tps_denominator: 1,
+                num_loops: 2,
+                have_timecodes: false,
+            })
+            .build()?;
+        let result: crate::encode::EncoderResult<u8> = encoder
+            .multiple(4, 4)?
+            .add_frame(&EncoderFrame::new(&red).duration(10))?
+            .add_frame(&EncoderFrame::new(&blue).duration(25))?
+            .encode()?;
+
+        let reader = JxlReader::from_bytes(decoder_builder().build()?, result.data)?;
+        assert!(matches!(reader.loop_count(), LoopCount::Finite(n) if n.get() == 2));
+        let frames = reader.into_frames().collect_frames()?;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].delay(), Delay::from_numer_denom_ms(100, 1));
+        assert_eq!(frames[1].delay(), Delay::from_numer_denom_ms(250, 1));
+        assert_eq!(frames[0].buffer().as_raw(), &red);
+        assert_eq!(frames[1].buffer().as_raw(), &blue);
+
+        Ok(())
+    }
+
+    struct Animation(Vec<Frame>);
+
+    impl<'a> AnimationDecoder<'a> for Animation {
+        fn into_frames(self) -> Frames<'a> {
+            Frames::new(Box::new(self.0.into_iter().map(Ok)))
+        }
+
+        fn loop_count(&self) -> LoopCount {
+            LoopCount::Finite(NonZeroU32::new(3).unwrap())
+        }
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn encode_animation() -> TestResult {
+        let red = image::Rgba([255, 0, 0, 255]);
+        let blue = image::Rgba([0, 0, 255, 255]);
+        let delays = [
+            Delay::from_numer_denom_ms(100, 1),
+            Delay::from_numer_denom_ms(100, 3),
+        ];
+        let animation = Animation(vec![
+            Frame::from_parts(RgbaImage::from_pixel(4, 4, red), 0, 0, delays[0]),
+            Frame::from_parts(RgbaImage::from_pixel(2, 2, blue), 1, 1, delays[1]),
+        ]);
+
+        let mut encoder = encoder_builder().lossless(true).build()?;
+        let data = encoder.encode_animation(animation)?;
+        assert!(!encoder.uses_original_profile);
+
+        let reader = JxlReader::from_bytes(decoder_builder().build()?, data)?;
+        assert!(matches!(reader.loop_count(), LoopCount::Finite(n) if n.get() == 3));
+        let frames = reader.into_frames().collect_frames()?;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].delay(), delays[0]);
+        assert_eq!(frames[1].delay(), delays[1]);
+        assert_eq!(frames[1].buffer().get_pixel(0, 0), &red);
+        assert_eq!(frames[1].buffer().get_pixel(2, 2), &blue);
+
+        assert_eq!(to_ticks(&delays), (3000, vec![300, 100]));
+        assert_eq!(
+            to_ticks(&[Delay::from_numer_denom_ms(1, u32::MAX)]),
+            (1000, vec![0])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(any(feature = "apng", feature = "gif"))]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn export() -> TestResult {
+        let frame = RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+        let delay = Delay::from_numer_denom_ms(100, 3);
+        let data = encoder_builder().build()?.encode_animation(Animation(vec![
+            Frame::from_parts(frame.clone(), 0, 0, delay),
+            Frame::from_parts(frame, 0, 0, delay),
+        ]))?;
+
+        #[cfg(feature = "apng")]
+        {
+            let mut apng = vec![];
+            JxlReader::from_bytes(decoder_builder().build()?, data.clone())?
+                .write_apng(&mut apng)?;
+            let reader = png::Decoder::new(std::io::Cursor::new(apng)).read_info()?;
+            let info = reader.info();
+            let control = info.animation_control.expect("APNG is animated");
+            assert_eq!((control.num_frames, control.num_plays), (2, 3));
+            let frame = info.frame_control.expect("APNG has frame control");
+            assert_eq!((frame.delay_num, frame.delay_den), (1, 30));
+            assert_eq!(to_apng_delay(Delay::from_numer_denom_ms(1, 3)), (1, 3000));
+            assert_eq!(to_apng_delay(Delay::from_numer_denom_ms(1, 100)), (0, 1000));
+            assert_eq!(
+                to_apng_delay(Delay::from_numer_denom_ms(u32::MAX, 1)),
+                (u16::MAX, 1)
+            );
+        }
+
+        #[cfg(feature = "gif")]
+        {
+            let mut gif = vec![];
+            JxlReader::from_bytes(decoder_builder().build()?, data)?.write_gif(&mut gif)?;
+            let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(gif))?;
+            assert!(matches!(decoder.loop_count(), LoopCount::Finite(n) if n.get() == 3));
+            let frames = decoder.into_frames().collect_frames()?;
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[0].delay(), Delay::from_numer_denom_ms(30, 1));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, coverage(off))]
+    fn encode() -> TestResult {
+        let sample = image::load_from_memory_with_format(SAMPLE_PNG, image::ImageFormat::Png)?;
+        let mut encoder = encoder_builder()
+            .lossless(true)
+            .uses_original_profile(true)
+            .build()?;
+        let decoder = decoder_builder().build()?;
+
+        for img in [
+            sample.clone(),
+            DynamicImage::ImageRgb8(sample.to_rgb8()),
+            DynamicImage::ImageLuma8(sample.to_luma8()),
+            DynamicImage::ImageLumaA16(sample.to_luma_alpha16()),
+            DynamicImage::ImageRgb32F(sample.to_rgb32f()),
+        ] {
+            let data = encoder.encode_dynamic(&img)?;
+            let (info, _) = decoder.decode_header(&data, false)?;
+            assert_eq!((info.xsize, info.ysize), (img.width(), img.height()));
+            assert_eq!(info.alpha_bits != 0, img.color().has_alpha());
+            assert_eq!(info.num_color_channels == 3, img.color().has_color());
+        }
+        assert!(encoder.color_encoding.is_none());
+
+        let mut data = vec![];
+        JxlWriter::new(&mut encoder, &mut data).write_image(
+            &[0b1010_0000, 0b0101_0000],
+            4,
+            2,
+            ExtendedColorType::L1,
+        )?;
+        let img = decoder
+            .decode_to_image(&data)?
+            .expect("Failed to create DynamicImage");
+        assert_eq!(img.to_luma8().as_raw(), &[255, 0, 255, 0, 0, 255, 0, 255]);
+
+        let mut data = vec![];
+        JxlWriter::new(&mut encoder, &mut data).write_image(
+            &[0, 128],
+            2,
+            1,
+            ExtendedColorType::A8,
+        )?;
+        let img = decoder
+            .decode_to_image(&data)?
+            .expect("Failed to create DynamicImage");
+        assert_eq!(img.to_luma_alpha8().as_raw(), &[255, 0, 255, 128]);
+
+        let mut data = vec![];
+        JxlWriter::new(&mut encoder, &mut data).write_image(
+            &0x7c1f_u16.to_le_bytes(),
+            1,
+            1,
+            ExtendedColorType::Rgb5x1,
+        )?;
+        let img = decoder
+            .decode_to_image(&data)?
+            .expect("Failed to create DynamicImage");
+        assert_eq!(img.to_rgb8().as_raw(), &[255, 0, 255]);
+
+        // CMYK needs a CMYK profile
+        assert!(JxlWriter::new(&mut encoder, vec![])
+            .write_image(&[0; 4], 1, 1, ExtendedColorType::Cmyk8)
+            .is_err());
+
+        let profile = lcms2::Profile::new_icc(&cmyk_profile())?.icc()?;
+        let mut data = vec![];
+        let mut writer = JxlWriter::new(&mut encoder, &mut data);
+        writer.set_icc_profile(profile)?;
+        writer.write_image(&[0; 16], 2, 1, ExtendedColorType::Cmyk16)?;
+        let (info, _) = decoder.decode_header(&data, false)?;
+        assert_eq!((info.num_color_channels, info.num_extra_channels), (3, 1));
+        assert!(encoder.extra_channels.is_empty());
+
+        Ok(())
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, coverage(off))]
     fn pixel_type() -> TestResult {
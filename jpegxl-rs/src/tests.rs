@@ -9,3 +9,59 @@ pub const SAMPLE_JXL: &[u8] = include_bytes!("../../samples/sample.jxl");
 const SAMPLE_JXL_JPEG: &[u8] = include_bytes!("../../samples/sample_jpg.jxl");
 pub const SAMPLE_JXL_GRAY: &[u8] = include_bytes!("../../samples/sample_grey.jxl");
 const SAMPLE_JXL_2BIT: &[u8] = include_bytes!("../../samples/2bit.jxl");
+
+/// Minimal CMYK output profile, with ink coverage darkening the L of Lab
+pub fn cmyk_profile() -> Vec<u8> {
+    fn be(value: usize) -> [u8; 4] {
+        u32::try_from(value).unwrap_or_default().to_be_bytes()
+    }
+
+    fn tag(kind: [u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = [kind.as_slice(), &[0; 4], body].concat();
+        data.resize(data.len().next_multiple_of(4), 0);
+        data
+    }
+
+    let desc = [&[0, 0, 0, 5][..], b"CMYK\0", &[0; 8 + 2 + 1 + 67]].concat();
+    let wtpt = [0x0000_f6d6_u32, 0x0001_0000, 0x0000_d32d].map(u32::to_be_bytes);
+    let mut lut = vec![4, 3, 2, 0];
+    for i in 0..9 {
+        let value: u32 = if i % 4 == 0 { 0x0001_0000 } else { 0 };
+        lut.extend(value.to_be_bytes());
+    }
+    let table: Vec<u8> = (0..=255).collect();
+    lut.extend(table.repeat(4));
+    for inks in 0..16u32 {
+        let lightness = 255 - 255 * inks.count_ones() / 4;
+        lut.extend([u8::try_from(lightness).unwrap_or_default(), 128, 128]);
+    }
+    lut.extend(table.repeat(3));
+
+    let tags = [
+        (b"desc", tag(*b"desc", &desc)),
+        (b"cprt", tag(*b"text", b"No copyright\0")),
+        (b"wtpt", tag(*b"XYZ ", &wtpt.concat())),
+        (b"A2B0", tag(*b"mft1", &lut)),
+    ];
+
+    let mut table = be(tags.len()).to_vec();
+    let mut body = vec![];
+    let mut offset = 128 + 4 + 12 * tags.len();
+    for (signature, data) in &tags {
+        table.extend(signature.as_slice());
+        table.extend(be(offset));
+        table.extend(be(data.len()));
+        offset += data.len();
+        body.extend(data);
+    }
+
+    let mut header = vec![0; 128];
+    header[..4].copy_from_slice(&be(offset));
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"prtr");
+    header[16..20].copy_from_slice(b"CMYK");
+    header[20..24].copy_from_slice(b"Lab ");
+    header[36..40].copy_from_slice(b"acsp");
+    header[68..80].copy_from_slice(&wtpt.concat());
+    [header, table, body].concat()
+}
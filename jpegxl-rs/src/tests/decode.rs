@@ -24,7 +24,7 @@ use testresult::TestResult;
 
 use crate::{
     common::Endianness,
-    decode::{Data, Metadata, PixelFormat, Pixels},
+    decode::{ChannelLayout, Data, Metadata, PixelFormat, Pixels},
     decoder_builder, DecodeError,
 };
 use crate::{ResizableRunner, ThreadsRunner};
@@ -119,6 +119,36 @@ fn pixel_types() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn image() -> TestResult {
+    let mut decoder = decoder_builder().build()?;
+    let (Metadata { width, height, .. }, packed) = decoder.decode_with::<u16>(super::SAMPLE_JXL)?;
+
+    // Odd alignment forces padding that is not a multiple of the sample size
+    decoder.pixel_format = Some(PixelFormat {
+        align: 7,
+        ..PixelFormat::default()
+    });
+    let image = decoder.decode_image::<u16>(super::SAMPLE_JXL)?;
+
+    assert_eq!((image.width(), image.height()), (width, height));
+    assert_eq!(image.layout(), ChannelLayout::Rgba);
+    assert!(image.stride() > (width * 4) as usize);
+    assert!(image.icc_profile().is_some());
+
+    let row_len = (width * 4) as usize;
+    assert_eq!(image.rows().len(), height as usize);
+    for (row, expected) in image.rows().zip(packed.chunks_exact(row_len)) {
+        assert_eq!(row, expected);
+    }
+    assert_eq!(image.pixel(1, 1), Some(&packed[row_len + 4..row_len + 8]));
+    assert!(image.pixel(width, 0).is_none());
+    assert!(image.row(height).is_none());
+    assert_eq!(image.into_packed(), packed);
+
+    Ok(())
+}
+
 #[test]
 fn jpeg() -> TestResult {
     let decoder = decoder_builder().init_jpeg_buffer(512).build()?;
@@ -15,20 +15,27 @@
  * along with jpegxl-rs.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::{cell::RefCell, io::Write, rc::Rc};
+
 use half::f16;
 use image::DynamicImage;
 use jpegxl_sys::color::color_encoding::{
     JxlColorEncoding, JxlColorSpace, JxlPrimaries, JxlRenderingIntent, JxlTransferFunction,
     JxlWhitePoint,
 };
+use jpegxl_sys::common::types::JxlBool;
 use pretty_assertions::assert_eq;
 use testresult::TestResult;
 
-use crate::decode::Data;
+use crate::decode::{Data, Orientation};
 use crate::{
     decoder_builder,
-    encode::{ColorEncoding, EncoderFrame, EncoderResult, Metadata},
-    encoder_builder, Endianness,
+    encode::{
+        Animation, BitDepth, BlendMode, ChunkedFrame, CodestreamLevel, ColorEncoding, Downsampled,
+        EncoderFrame, EncoderResult, EncoderStats, EncodingMode, ExtraChannel, ExtraChannelType,
+        FrameSettings, GroupOrder, JpegOptions, Metadata, Resampling, SizeTarget, UpsamplingMode,
+    },
+    encoder_builder, EncodeError, Endianness,
 };
 use crate::{encode::EncoderSpeed, ResizableRunner, ThreadsRunner};
 
@@ -71,6 +78,40 @@ fn jpeg() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn jpeg_options() -> TestResult {
+    let mut encoder = encoder_builder()
+        .jpeg_options(JpegOptions {
+            cfl: Some(false),
+            compress_boxes: Some(true),
+            verify: true,
+            ..Default::default()
+        })
+        .build()?;
+    let _res = encoder.encode_jpeg(super::SAMPLE_JPEG)?;
+
+    encoder.jpeg_options = JpegOptions {
+        store_reconstruction: false,
+        keep_exif: false,
+        keep_xmp: false,
+        keep_jumbf: false,
+        ..Default::default()
+    };
+    let res = encoder.encode_jpeg(super::SAMPLE_JPEG)?;
+    let (_, Data::Pixels(_)) = decoder_builder().build()?.reconstruct(&res)? else {
+        panic!("JPEG reconstruction data stored");
+    };
+
+    // Verifying needs the reconstruction data
+    encoder.jpeg_options.verify = true;
+    assert!(matches!(
+        encoder.encode_jpeg(super::SAMPLE_JPEG),
+        Err(EncodeError::ApiUsage)
+    ));
+
+    Ok(())
+}
+
 #[test]
 fn metadata() -> TestResult {
     let sample = get_sample().to_rgb8();
@@ -123,6 +164,75 @@ fn builder() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn separate_alpha() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let alpha = vec![u16::MAX / 2; (sample.width() * sample.height()) as usize];
+    let mut encoder = encoder_builder()
+        .has_alpha(true)
+        .alpha_premultiplied(true)
+        .alpha_bits(16)
+        .alpha_distance(0.0)
+        .build()?;
+
+    let frame = EncoderFrame::new(sample.as_raw()).alpha(&alpha);
+    let res: EncoderResult<u8> = encoder.encode_frame(&frame, sample.width(), sample.height())?;
+
+    let decoder = decoder_builder().build()?;
+    let (info, _) = decoder.decode_header(&res, false)?;
+    assert_eq!(info.alpha_bits, 16);
+    assert_eq!(info.alpha_premultiplied, JxlBool::True);
+    let _res = decoder.decode(&res)?;
+
+    // The alpha plane needs an alpha channel
+    encoder.has_alpha = false;
+    assert!(matches!(
+        encoder.encode_frame::<_, u8>(&frame, sample.width(), sample.height()),
+        Err(EncodeError::ApiUsage)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn retry_after_error() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let (width, height) = (sample.width(), sample.height());
+    let alpha = vec![u8::MAX; (width * height) as usize];
+    let mut encoder = encoder_builder()
+        .color_encoding(ColorEncoding::LinearSrgb)
+        .codestream_level(CodestreamLevel::Level5)
+        .build()?;
+
+    // Each failed encode leaves the encoder ready for the next one
+    let frame = EncoderFrame::new(sample.as_raw()).alpha(&alpha);
+    assert!(matches!(
+        encoder.encode_frame::<_, u8>(&frame, width, height),
+        Err(EncodeError::ApiUsage)
+    ));
+    let _res: EncoderResult<u8> = encoder.encode(sample.as_raw(), width, height)?;
+
+    assert!(matches!(
+        encoder.encode::<u8, f32>(sample.as_raw(), width, height),
+        Err(EncodeError::CodestreamLevel { .. })
+    ));
+    let _res: EncoderResult<u8> = encoder.encode(sample.as_raw(), width, height)?;
+
+    encoder.downsampled = Some(Downsampled {
+        factor: Resampling::X2,
+        upsampling: UpsamplingMode::NearestNeighbor,
+    });
+    assert!(matches!(
+        encoder.encode::<u8, u8>(sample.as_raw(), width, height),
+        Err(EncodeError::BadInput)
+    ));
+    encoder.downsampled = None;
+    let result: EncoderResult<u8> = encoder.encode(sample.as_raw(), width, height)?;
+    _ = decoder_builder().build()?.decode(&result)?;
+
+    Ok(())
+}
+
 #[test]
 fn resizable() -> TestResult {
     let resizable_runner = ResizableRunner::default();
@@ -191,6 +301,218 @@ fn multi_frames() -> TestResult {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "image")]
+fn animation() -> TestResult {
+    use jpegxl_sys::common::types::JxlDataType;
+
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder()
+        .animation(Animation {
+            tps_numerator: 100,
+            tps_denominator: 1,
+            num_loops: 2,
+            have_timecodes: true,
+        })
+        .build()?;
+
+    let frame = EncoderFrame::new(sample.as_raw());
+    let result: EncoderResult<u8> = encoder
+        .multiple(sample.width(), sample.height())?
+        .add_frame(&frame.clone().duration(10).timecode(0x0000_0001))?
+        .add_frame(&frame.duration(20).timecode(0x0000_0002).name("second"))?
+        .encode()?;
+
+    let decoder = decoder_builder().build()?;
+    let (info, _) = decoder.decode_header(&result, false)?;
+    assert_eq!(info.have_animation, JxlBool::True);
+    assert_eq!(info.animation.num_loops, 2);
+    let frames = decoder.decode_frames(&result, JxlDataType::Uint8, 3)?;
+    let headers: Vec<_> = frames
+        .iter()
+        .map(|(header, _)| (header.duration, header.timecode, header.name_length))
+        .collect();
+    assert_eq!(headers, [(10, 1, 0), (20, 2, 6)]);
+
+    assert!(EncoderFrame::new(sample.as_raw())
+        .name("\0")
+        .c_name()
+        .is_some_and(|name| name.is_err()));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "image")]
+fn frame_header_reset() -> TestResult {
+    use jpegxl_sys::common::types::JxlDataType;
+
+    let full = [0u8, 255, 0].repeat(16);
+    let mut encoder = encoder_builder()
+        .animation(Animation::default())
+        .lossless(true)
+        .uses_original_profile(true)
+        .build()?;
+    let result: EncoderResult<u8> = encoder
+        .multiple(4, 4)?
+        .add_frame(
+            &EncoderFrame::new(&[255u8, 0, 0].repeat(4))
+                .duration(10)
+                .name("first")
+                .crop(1, 1, 2, 2),
+        )?
+        .add_frame(&EncoderFrame::new(&full))?
+        .encode()?;
+
+    let frames = decoder_builder()
+        .build()?
+        .decode_frames(&result, JxlDataType::Uint8, 3)?;
+    let headers: Vec<_> = frames
+        .iter()
+        .map(|(header, _)| (header.duration, header.name_length))
+        .collect();
+    assert_eq!(headers, [(10, 5), (0, 0)]);
+    assert_eq!(frames[1].1, full);
+
+    Ok(())
+}
+
+#[test]
+fn layers() -> TestResult {
+    let base = [255u8, 0, 0].repeat(16);
+    let layer = [0u8, 0, 255].repeat(4);
+    let mut encoder = encoder_builder()
+        .lossless(true)
+        .uses_original_profile(true)
+        .build()?;
+
+    let result: EncoderResult<u8> = encoder
+        .multiple(4, 4)?
+        .add_frame(&EncoderFrame::new(&base).save_as_reference(1))?
+        .add_frame(
+            &EncoderFrame::new(&layer)
+                .crop(1, 1, 2, 2)
+                .blend_mode(BlendMode::Replace)
+                .blend_source(1),
+        )?
+        .encode()?;
+
+    let decoder = decoder_builder().build()?;
+    let (metadata, pixels) = decoder.decode_with::<u8>(&result)?;
+    assert_eq!((metadata.width, metadata.height), (4, 4));
+    assert_eq!(&pixels[..3], &[255, 0, 0]);
+    assert_eq!(&pixels[(4 + 1) * 3..(4 + 1) * 3 + 3], &[0, 0, 255]);
+    assert_eq!(&pixels[(3 * 4 + 3) * 3..], &[255, 0, 0]);
+
+    Ok(())
+}
+
+#[test]
+fn blend_alpha() -> TestResult {
+    let base = [255u8, 0, 0, 255].repeat(16);
+    // Opaque and transparent blue columns
+    let layer = [[0u8, 0, 255, 255], [0, 0, 255, 0]].concat().repeat(2);
+    let mut encoder = encoder_builder()
+        .has_alpha(true)
+        .lossless(true)
+        .uses_original_profile(true)
+        .build()?;
+
+    let result: EncoderResult<u8> = encoder
+        .multiple(4, 4)?
+        .add_frame(
+            &EncoderFrame::new(&base)
+                .num_channels(4)
+                .save_as_reference(3),
+        )?
+        .add_frame(
+            &EncoderFrame::new(&layer)
+                .num_channels(4)
+                .crop(1, 1, 2, 2)
+                .blend_mode(BlendMode::Blend)
+                .blend_source(3)
+                .blend_alpha(0)
+                .blend_clamp(true),
+        )?
+        .encode()?;
+
+    let (_, pixels) = decoder_builder().build()?.decode_with::<u8>(&result)?;
+    assert_eq!(&pixels[(4 + 1) * 4..(4 + 2) * 4], &[0, 0, 255, 255]);
+    assert_eq!(&pixels[(4 + 2) * 4..(4 + 3) * 4], &[255, 0, 0, 255]);
+
+    Ok(())
+}
+
+#[test]
+fn frame_settings_per_frame() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let (width, height) = (sample.width(), sample.height());
+    let layer = [0u8, 0, 255].repeat(64);
+    let mut encoder = encoder_builder()
+        .quality(3.0)
+        .uses_original_profile(true)
+        .build()?;
+
+    let result: EncoderResult<u8> = encoder
+        .multiple(width, height)?
+        .add_frame(&EncoderFrame::new(sample.as_raw()).speed(EncoderSpeed::Lightning))?
+        .add_frame(
+            &EncoderFrame::new(&layer)
+                .crop(0, 0, 8, 8)
+                .blend_mode(BlendMode::Replace)
+                .lossless(true),
+        )?
+        .encode()?;
+
+    let decoder = decoder_builder().build()?;
+    let (_, pixels) = decoder.decode_with::<u8>(&result)?;
+    assert_eq!(&pixels[..3], &[0, 0, 255]);
+    assert_eq!(&pixels[7 * 3..8 * 3], &[0, 0, 255]);
+
+    Ok(())
+}
+
+#[test]
+fn extra_channels() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let depth = get_sample().to_luma8();
+    let spot = vec![128u8; depth.len()];
+    let mut encoder = encoder_builder()
+        .extra_channels(vec![
+            ExtraChannel::new(ExtraChannelType::Depth)
+                .name("depth")
+                .distance(0.0),
+            ExtraChannel::new(ExtraChannelType::SpotColor {
+                color: [1.0, 0.0, 0.0],
+                solidity: 0.5,
+            }),
+        ])
+        .build()?;
+
+    let frame = EncoderFrame::new(sample.as_raw())
+        .extra_channel(depth.as_raw())
+        .extra_channel(&spot);
+    let result: EncoderResult<u8> =
+        encoder.encode_frame(&frame, sample.width(), sample.height())?;
+
+    let decoder = decoder_builder().build()?;
+    let (info, _) = decoder.decode_header(&result, false)?;
+    assert_eq!(info.num_extra_channels, 2);
+    assert_eq!(info.alpha_bits, 0);
+    let _res = decoder.decode(&result)?;
+
+    // Every extra channel needs its pixels
+    assert!(encoder
+        .encode_frame::<_, u8>(
+            &EncoderFrame::new(sample.as_raw()),
+            sample.width(),
+            sample.height()
+        )
+        .is_err());
+
+    Ok(())
+}
+
 #[test]
 fn gray() -> TestResult {
     let sample = get_sample().to_luma8();
@@ -222,6 +544,360 @@ fn gray() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn frame_settings() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let decoder = decoder_builder().build()?;
+    let mut encoder = encoder_builder()
+        .frame_settings(FrameSettings {
+            resampling: Some(Resampling::X2),
+            epf: Some(2),
+            gaborish: Some(false),
+            patches: Some(false),
+            dots: Some(false),
+            photon_noise_iso: Some(400.0),
+            group_order: Some(GroupOrder::CenterFirst {
+                x: Some(10),
+                y: None,
+            }),
+            mode: Some(EncodingMode::VarDct),
+            ..Default::default()
+        })
+        .build()?;
+
+    // Settings are kept between encodes
+    for _ in 0..2 {
+        let result: EncoderResult<u8> =
+            encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+        _ = decoder.decode(&result)?;
+    }
+
+    encoder.set_frame_float_option(
+        jpegxl_sys::encoder::encode::JxlEncoderFrameSettingId::ModularMaTreeLearningPercent,
+        25.0,
+    )?;
+    encoder.frame_settings.mode = Some(EncodingMode::Modular);
+    encoder.frame_settings.group_order = Some(GroupOrder::Scanline);
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    _ = decoder.decode(&result)?;
+
+    encoder.frame_settings.epf = Some(4);
+    assert!(matches!(
+        encoder.encode::<u8, u8>(sample.as_raw(), sample.width(), sample.height()),
+        Err(EncodeError::NotSupported)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn stats() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder().collect_stats(true).build()?;
+
+    let mut total = EncoderStats::default();
+    for _ in 0..2 {
+        let result: EncoderResult<u8> =
+            encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+        let stats = result.stats.expect("stats not collected");
+        assert!(stats.total_bits() > 0);
+        assert!(stats.ac_bits > 0);
+        total += &stats;
+    }
+    assert!(total.total_bits() > 0);
+
+    encoder.collect_stats = false;
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert!(result.stats.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn codestream_level() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let mut encoder = encoder_builder()
+        .codestream_level(CodestreamLevel::Level5)
+        .build()?;
+
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert_eq!(result.required_codestream_level, Some(5));
+
+    // 32-bit float samples need level 10
+    assert!(matches!(
+        encoder.encode::<u8, f32>(sample.as_raw(), sample.width(), sample.height()),
+        Err(EncodeError::CodestreamLevel { required: Some(10) })
+    ));
+
+    encoder.codestream_level = CodestreamLevel::Level10;
+    let result: EncoderResult<f32> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    assert_eq!(result.required_codestream_level, Some(10));
+    _ = decoder_builder().build()?.decode(&result)?;
+
+    // Level 10 is signalled in a `jxll` box
+    let result = encoder.encode_jpeg(super::SAMPLE_JPEG)?;
+    assert!(result.windows(4).any(|w| w == b"jxll"));
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::float_cmp)]
+fn size_budget() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let (width, height) = (sample.width(), sample.height());
+    let frame = EncoderFrame::new(sample.as_raw());
+    let mut encoder = encoder_builder().speed(EncoderSpeed::Lightning).build()?;
+
+    let target = SizeTarget::BitsPerPixel(1.0);
+    let (result, distance): (EncoderResult<u8>, _) =
+        encoder.encode_with_budget(&frame, width, height, target, 6)?;
+    assert!(result.len() * 8 <= (width * height) as usize);
+    assert!((0.01..=25.0).contains(&distance));
+    assert_eq!(encoder.quality, 1.0);
+    _ = decoder_builder().build()?.decode(&result)?;
+
+    assert!(matches!(
+        encoder.encode_with_budget::<_, u8>(&frame, width, height, SizeTarget::Bytes(10), 6),
+        Err(EncodeError::SizeBudget)
+    ));
+
+    // Settings overriding the distance, or no iterations
+    assert!(matches!(
+        encoder.encode_with_budget::<_, u8>(&frame, width, height, target, 0),
+        Err(EncodeError::ApiUsage)
+    ));
+    let lossless = frame.clone().lossless(true);
+    assert!(matches!(
+        encoder.encode_with_budget::<_, u8>(&lossless, width, height, target, 6),
+        Err(EncodeError::ApiUsage)
+    ));
+    encoder.lossless = Some(true);
+    assert!(matches!(
+        encoder.encode_with_budget::<_, u8>(&frame, width, height, target, 6),
+        Err(EncodeError::ApiUsage)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn downsampled() -> TestResult {
+    let sample = get_sample();
+    let (width, height) = (sample.width(), sample.height());
+    let small = sample
+        .resize_exact(
+            width.div_ceil(4),
+            height.div_ceil(4),
+            image::imageops::FilterType::Triangle,
+        )
+        .to_rgb8();
+    let mut encoder = encoder_builder()
+        .downsampled(Downsampled {
+            factor: Resampling::X4,
+            upsampling: UpsamplingMode::NearestNeighbor,
+        })
+        .build()?;
+
+    let result: EncoderResult<u8> = encoder.encode(small.as_raw(), width, height)?;
+    let (metadata, _) = decoder_builder().build()?.decode(&result)?;
+    assert_eq!((metadata.width, metadata.height), (width, height));
+
+    // Full resolution input is rejected
+    let full = sample.to_rgb8();
+    assert!(matches!(
+        encoder.encode::<u8, u8>(full.as_raw(), width, height),
+        Err(EncodeError::BadInput)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn expert_options() -> TestResult {
+    let sample = image::imageops::thumbnail(&get_sample().to_rgb8(), 32, 32);
+    let mut encoder = encoder_builder()
+        .lossless(true)
+        .uses_original_profile(true)
+        .speed(EncoderSpeed::TectonicPlate)
+        .build()?;
+
+    // Effort 11 is only allowed in expert mode
+    assert!(matches!(
+        encoder.encode::<u8, u8>(sample.as_raw(), sample.width(), sample.height()),
+        Err(EncodeError::ApiUsage)
+    ));
+
+    encoder.expert_options = true;
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    _ = decoder_builder().build()?.decode(&result)?;
+
+    Ok(())
+}
+
+#[test]
+fn bit_depth() -> TestResult {
+    let sample = get_sample().to_rgb16();
+    let raw: Vec<u16> = sample.as_raw().iter().map(|v| v >> 4).collect();
+    let mut encoder = encoder_builder()
+        .lossless(true)
+        .uses_original_profile(true)
+        .bit_depth(BitDepth::Uint {
+            bits: 12,
+            raw_range: true,
+        })
+        .build()?;
+
+    let result: EncoderResult<u16> = encoder.encode(&raw, sample.width(), sample.height())?;
+    let decoder = decoder_builder().build()?;
+    let (info, _) = decoder.decode_header(&result, false)?;
+    assert_eq!(info.bits_per_sample, 12);
+    let (_, pixels) = decoder.decode_with::<u16>(&result)?;
+    assert_eq!(pixels[0] >> 4, sample.as_raw()[0] >> 4);
+
+    encoder.lossless = None;
+    encoder.bit_depth = Some(BitDepth::Float {
+        bits: 24,
+        exponent_bits: 8,
+    });
+    let sample = get_sample().to_rgb32f();
+    let result: EncoderResult<f32> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+    let (info, _) = decoder.decode_header(&result, false)?;
+    assert_eq!(
+        (info.bits_per_sample, info.exponent_bits_per_sample),
+        (24, 8)
+    );
+
+    encoder.bit_depth = Some(BitDepth::Uint {
+        bits: 17,
+        raw_range: false,
+    });
+    assert!(matches!(
+        encoder.encode::<f32, u16>(sample.as_raw(), sample.width(), sample.height()),
+        Err(EncodeError::NotSupported)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn live() -> TestResult {
+    // Writer shared with the test, to watch the output while the session is open
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let sample = get_sample().to_rgb8();
+    let (width, height) = (sample.width(), sample.height());
+    let mut encoder = encoder_builder().animation(Animation::default()).build()?;
+
+    let output = Rc::new(RefCell::new(vec![]));
+    let mut session = encoder.live::<u8, _>(width, height, Shared(Rc::clone(&output)))?;
+    let mut written = 0;
+    for i in 1..=3 {
+        session.add_frame(&EncoderFrame::new(sample.as_raw()).duration(100 * i))?;
+        // Each frame is held back until the next one
+        let len = output.borrow().len();
+        assert!(i == 1 || len > written);
+        written = len;
+    }
+    session.finish()?;
+    assert!(output.borrow().len() > written);
+
+    let data = output.take();
+    let (info, _) = decoder_builder().build()?.decode_header(&data, false)?;
+    assert_eq!(info.have_animation, JxlBool::True);
+    _ = decoder_builder().build()?.decode(&data)?;
+
+    #[cfg(feature = "image")]
+    {
+        use jpegxl_sys::common::types::JxlDataType;
+
+        let frames = decoder_builder()
+            .build()?
+            .decode_frames(&data, JxlDataType::Uint8, 3)?;
+        let headers: Vec<_> = frames
+            .iter()
+            .map(|(header, pixels)| (header.duration, header.is_last, pixels.len()))
+            .collect();
+        let len = sample.len();
+        assert_eq!(
+            headers,
+            [
+                (100, JxlBool::False, len),
+                (200, JxlBool::False, len),
+                (300, JxlBool::True, len)
+            ]
+        );
+    }
+
+    // An abandoned session leaves the encoder usable
+    let mut session = encoder.live::<u8, _>(width, height, vec![])?;
+    session.add_frame(&EncoderFrame::new(sample.as_raw()))?;
+    drop(session);
+    let _: EncoderResult<u8> = encoder.encode(sample.as_raw(), width, height)?;
+
+    // A writing error is returned until the end of the session
+    let frame = EncoderFrame::new(sample.as_raw()).duration(100);
+    let mut session = encoder.live::<u8, _>(width, height, Failing)?;
+    session.add_frame(&frame)?;
+    assert!(matches!(session.add_frame(&frame), Err(EncodeError::Io(_))));
+    assert!(matches!(session.add_frame(&frame), Err(EncodeError::Io(_))));
+    assert!(matches!(session.finish(), Err(EncodeError::Io(_))));
+
+    Ok(())
+}
+
+#[test]
+fn orientation() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let mut exif = vec![0, 0, 0, 0];
+    exif.extend_from_slice(b"MM\0\x2a\0\0\0\x08");
+    exif.extend_from_slice(&[0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+    let mut encoder = encoder_builder()
+        .maybe_orientation(Metadata::Exif(&exif).exif_orientation())
+        .intrinsic_size((sample.width() / 2, sample.height() / 2))
+        .build()?;
+
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+
+    let (info, _) = decoder_builder().build()?.decode_header(&result, false)?;
+    assert_eq!(info.orientation, Orientation::Rotate90Cw);
+    assert_eq!(
+        (info.intrinsic_xsize, info.intrinsic_ysize),
+        (sample.width() / 2, sample.height() / 2)
+    );
+
+    Ok(())
+}
+
 #[test]
 fn initial_buffer() -> TestResult {
     let mut encoder = encoder_builder().init_buffer_size(0).build()?;
@@ -261,3 +937,163 @@ fn custom_color_encoding() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+fn icc_profile() -> TestResult {
+    use crate::decode::Metadata;
+
+    let sample = get_sample().to_rgb8();
+    let profile = lcms2::Profile::new_srgb().icc()?;
+    let mut encoder = encoder_builder()
+        .color_encoding(ColorEncoding::Icc(profile.clone()))
+        .uses_original_profile(true)
+        .build()?;
+
+    let result: EncoderResult<u8> =
+        encoder.encode(sample.as_raw(), sample.width(), sample.height())?;
+
+    let decoder = decoder_builder().icc_profile(true).build()?;
+    let (Metadata { icc_profile, .. }, _) = decoder.decode(&result)?;
+    assert_eq!(icc_profile, Some(profile));
+
+    encoder.color_encoding = Some(ColorEncoding::Icc(vec![0; 16]));
+    assert!(matches!(
+        encoder.encode::<u8, u8>(sample.as_raw(), sample.width(), sample.height()),
+        Err(EncodeError::InvalidIccProfile)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn cmyk() -> TestResult {
+    let profile = lcms2::Profile::new_icc(&super::cmyk_profile())?;
+    assert_eq!(profile.color_space(), lcms2::ColorSpaceSignature::CmykData);
+    let profile = profile.icc()?;
+
+    let sample = get_sample().to_rgb8();
+    let black = get_sample().to_luma8();
+    let mut encoder = encoder_builder()
+        .color_encoding(ColorEncoding::Icc(profile.clone()))
+        .uses_original_profile(true)
+        .build()?;
+    let frame = EncoderFrame::new(sample.as_raw()).extra_channel(black.as_raw());
+
+    // The K channel is required
+    assert!(matches!(
+        encoder.encode_frame::<_, u8>(&frame, sample.width(), sample.height()),
+        Err(EncodeError::ApiUsage)
+    ));
+
+    encoder.extra_channels = vec![ExtraChannel::new(ExtraChannelType::Black)];
+    let result: EncoderResult<u8> =
+        encoder.encode_frame(&frame, sample.width(), sample.height())?;
+
+    let decoder = decoder_builder().build()?;
+    let (info, icc_profile) = decoder.decode_header(&result, true)?;
+    assert_eq!(info.num_extra_channels, 1);
+    assert_eq!(icc_profile, Some(profile));
+
+    Ok(())
+}
+
+#[test]
+fn writer() -> TestResult {
+    let sample = get_sample().to_rgb8();
+    let frame = EncoderFrame::new(sample.as_raw());
+    let mut encoder = encoder_builder().use_container(true).build()?;
+
+    let mut cursor = std::io::Cursor::new(vec![0xff]);
+    cursor.set_position(1);
+    encoder.encode_to_writer::<_, u8>(&frame, sample.width(), sample.height(), &mut cursor)?;
+    assert_eq!(cursor.position(), cursor.get_ref().len() as u64);
+
+    let mut stream = vec![];
+    encoder.encode_to_stream::<_, u8>(&frame, sample.width(), sample.height(), &mut stream)?;
+    assert_eq!(&cursor.get_ref()[1..], &stream[..]);
+
+    let decoder = decoder_builder().build()?;
+    let _res = decoder.decode(&stream)?;
+
+    Ok(())
+}
+
+#[test]
+fn chunked() -> TestResult {
+    struct Tiles(image::RgbImage, usize);
+
+    impl ChunkedFrame for Tiles {
+        type Pixel = u8;
+
+        fn num_channels(&self) -> u32 {
+            3
+        }
+
+        fn get_color_channel_data_at(
+            &mut self,
+            x: usize,
+            y: usize,
+            width: usize,
+            height: usize,
+        ) -> Vec<u8> {
+            self.1 += 1;
+            let stride = self.0.width() as usize * 3;
+            self.0
+                .as_raw()
+                .chunks_exact(stride)
+                .skip(y)
+                .take(height)
+                .flat_map(|row| &row[x * 3..(x + width) * 3])
+                .copied()
+                .collect()
+        }
+    }
+
+    let sample = get_sample().to_rgb8();
+    let (width, height) = sample.dimensions();
+    let mut tiles = Tiles(sample, 0);
+    let mut encoder = encoder_builder()
+        .lossless(true)
+        .uses_original_profile(true)
+        .build()?;
+    let decoder = decoder_builder().build()?;
+
+    let result: EncoderResult<u8> = encoder.encode_chunked(&mut tiles, width, height)?;
+    assert!(tiles.1 > 0);
+    let (_, pixels) = decoder.decode_with::<u8>(&result)?;
+    assert_eq!(pixels, *tiles.0.as_raw());
+
+    let mut cursor = std::io::Cursor::new(vec![]);
+    encoder.encode_chunked_to_writer::<_, u8>(&mut tiles, width, height, &mut cursor)?;
+    let (_, pixels) = decoder.decode_with::<u8>(cursor.get_ref())?;
+    assert_eq!(pixels, *tiles.0.as_raw());
+
+    Ok(())
+}
+
+#[test]
+fn chunked_failure() -> TestResult {
+    // Panics, or returns nothing
+    struct Broken(bool);
+
+    impl ChunkedFrame for Broken {
+        type Pixel = u8;
+
+        fn num_channels(&self) -> u32 {
+            3
+        }
+
+        fn get_color_channel_data_at(&mut self, _: usize, _: usize, _: usize, _: usize) -> Vec<u8> {
+            assert!(!self.0, "no pixels");
+            vec![]
+        }
+    }
+
+    for panics in [true, false] {
+        let mut encoder = encoder_builder().build()?;
+        let result = encoder.encode_chunked::<_, u8>(&mut Broken(panics), 64, 64);
+        assert!(matches!(result, Err(EncodeError::ChunkedFrame(_))));
+    }
+
+    Ok(())
+}